@@ -1,16 +1,17 @@
 use anyhow::{bail, Result};
-use serde::{Serialize, Serializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
 use std::{fmt, result};
 
+pub mod nbt;
 pub mod net;
 pub mod player;
 pub mod registry;
 pub mod text;
 pub mod world;
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct Identifier {
     namespace: Cow<'static, str>,
     path: Cow<'static, str>,
@@ -28,7 +29,7 @@ impl Identifier {
         if !namespace.chars().all(valid) {
             bail!("the namespace contains invalid characters");
         }
-        if !namespace.chars().all(|c| valid(c) || c == '/') {
+        if !path.chars().all(|c| valid(c) || c == '/') {
             bail!("the path contains invalid characters");
         }
 
@@ -39,6 +40,28 @@ impl Identifier {
         Ok(identifier)
     }
 
+    pub fn minecraft(path: impl Into<String>) -> Result<Self> {
+        Self::new(Self::MINECRAFT, path)
+    }
+
+    pub fn minestodon(path: impl Into<String>) -> Result<Self> {
+        Self::new(Self::MINESTODON, path)
+    }
+
+    // Panics on invalid characters, which fails the build when used in a constant
+    pub const fn new_const(namespace: &'static str, path: &'static str) -> Self {
+        if !valid_const_chars(namespace.as_bytes(), false) {
+            panic!("the namespace contains invalid characters");
+        }
+        if !valid_const_chars(path.as_bytes(), true) {
+            panic!("the path contains invalid characters");
+        }
+        Self {
+            namespace: Cow::Borrowed(namespace),
+            path: Cow::Borrowed(path),
+        }
+    }
+
     /// # Safety
     ///
     /// The given path must contain only lowercase alphanumeric characters, dots (`.`),
@@ -59,6 +82,19 @@ impl Identifier {
     }
 }
 
+const fn valid_const_chars(bytes: &[u8], allow_slash: bool) -> bool {
+    let mut index = 0;
+    while index < bytes.len() {
+        let valid = matches!(bytes[index], b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_')
+            || (allow_slash && bytes[index] == b'/');
+        if !valid {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
 impl Display for Identifier {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}:{}", self.namespace, self.path)
@@ -73,3 +109,52 @@ impl Serialize for Identifier {
         serializer.serialize_str(&self.to_string())
     }
 }
+
+impl<'de> Deserialize<'de> for Identifier {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let str = <Cow<'de, str>>::deserialize(deserializer)?;
+        Self::parse(&str).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONST_ID: Identifier = Identifier::new_const(Identifier::MINESTODON, "menus/home");
+
+    #[test]
+    fn runtime_constructors() -> Result<()> {
+        assert_eq!(
+            "minecraft:plains",
+            Identifier::minecraft("plains")?.to_string()
+        );
+        assert_eq!(CONST_ID, Identifier::minestodon("menus/home")?);
+        assert!(Identifier::minestodon("Home Menu").is_err());
+        assert!(Identifier::minecraft("plains!").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn path_validation() {
+        assert!(Identifier::new("minestodon", "foo bar").is_err());
+        assert!(Identifier::new("mine/stodon", "foo").is_err());
+        assert!(Identifier::new("minestodon", "a/b").is_ok());
+    }
+
+    #[test]
+    fn serde_round_trip() -> Result<()> {
+        let id = Identifier::minestodon("menus/home")?;
+        let json = serde_json::to_string(&id)?;
+        assert_eq!("\"minestodon:menus/home\"", json);
+        assert_eq!(id, serde_json::from_str(&json)?);
+
+        let stone = serde_json::from_str::<Identifier>("\"stone\"")?;
+        assert_eq!(Identifier::minecraft("stone")?, stone);
+        assert!(serde_json::from_str::<Identifier>("\"Stone Bricks\"").is_err());
+        Ok(())
+    }
+}
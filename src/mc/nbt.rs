@@ -0,0 +1,202 @@
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use fastnbt::{ByteArray, IntArray, LongArray};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::io::Read;
+use std::result;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NbtValue {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<NbtValue>),
+    Compound(HashMap<String, NbtValue>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl NbtValue {
+    // Vanilla stops at the same depth
+    pub const MAX_DEPTH: usize = 512;
+
+    // Reads a named root tag, which is what 1.19.3 sends; its name is always empty. A lone end
+    // tag is how slots and the like say there's no NBT at all
+    pub fn read<R: Read + ?Sized>(buf: &mut R) -> Result<Option<Self>> {
+        let tag = buf.read_u8().context("failed to read the root tag type")?;
+        if tag == 0 {
+            return Ok(None);
+        }
+        read_string(buf).context("failed to read the root name")?;
+        Self::read_payload(buf, tag, 0).map(Some)
+    }
+
+    fn read_payload<R: Read + ?Sized>(buf: &mut R, tag: u8, depth: usize) -> Result<Self> {
+        if depth > Self::MAX_DEPTH {
+            bail!("the NBT is nested deeper than {} levels", Self::MAX_DEPTH);
+        }
+
+        let value = match tag {
+            1 => Self::Byte(buf.read_i8()?),
+            2 => Self::Short(buf.read_i16::<BigEndian>()?),
+            3 => Self::Int(buf.read_i32::<BigEndian>()?),
+            4 => Self::Long(buf.read_i64::<BigEndian>()?),
+            5 => Self::Float(buf.read_f32::<BigEndian>()?),
+            6 => Self::Double(buf.read_f64::<BigEndian>()?),
+            7 => Self::ByteArray(read_array(buf, |buf| Ok(buf.read_i8()?))?),
+            8 => Self::String(read_string(buf)?),
+            9 => {
+                let element_tag = buf.read_u8().context("failed to read the list type")?;
+                let list = read_array(buf, |buf| Self::read_payload(buf, element_tag, depth + 1))
+                    .context("failed to read a list element")?;
+                Self::List(list)
+            }
+            10 => {
+                let mut compound = HashMap::new();
+                loop {
+                    let tag = buf.read_u8().context("failed to read the entry type")?;
+                    if tag == 0 {
+                        break;
+                    }
+                    let name = read_string(buf).context("failed to read the entry name")?;
+                    let value = Self::read_payload(buf, tag, depth + 1)
+                        .with_context(|| format!("failed to read the {name:?} entry"))?;
+                    compound.insert(name, value);
+                }
+                Self::Compound(compound)
+            }
+            11 => Self::IntArray(read_array(buf, |buf| Ok(buf.read_i32::<BigEndian>()?))?),
+            12 => Self::LongArray(read_array(buf, |buf| Ok(buf.read_i64::<BigEndian>()?))?),
+            tag => bail!("invalid NBT tag type {tag}"),
+        };
+        Ok(value)
+    }
+}
+
+// Elements are read one at a time so a huge length can't allocate before the data runs out
+fn read_array<R, T>(buf: &mut R, mut read: impl FnMut(&mut R) -> Result<T>) -> Result<Vec<T>>
+where
+    R: Read + ?Sized,
+{
+    let len = buf
+        .read_i32::<BigEndian>()
+        .context("failed to read the length")?;
+    if len < 0 {
+        bail!("the length is negative ({len})");
+    }
+    let mut elements = vec![];
+    for _ in 0..len {
+        elements.push(read(buf)?);
+    }
+    Ok(elements)
+}
+
+// Java's modified UTF-8 only differs from UTF-8 for NUL and characters outside the BMP
+fn read_string<R: Read + ?Sized>(buf: &mut R) -> Result<String> {
+    let len = buf.read_u16::<BigEndian>()?;
+    let mut bytes = vec![0; len.into()];
+    buf.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).context("the string isn't valid UTF-8")
+}
+
+impl Serialize for NbtValue {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Byte(byte) => serializer.serialize_i8(*byte),
+            Self::Short(short) => serializer.serialize_i16(*short),
+            Self::Int(int) => serializer.serialize_i32(*int),
+            Self::Long(long) => serializer.serialize_i64(*long),
+            Self::Float(float) => serializer.serialize_f32(*float),
+            Self::Double(double) => serializer.serialize_f64(*double),
+            Self::ByteArray(bytes) => ByteArray::new(bytes.clone()).serialize(serializer),
+            Self::String(string) => serializer.serialize_str(string),
+            Self::List(list) => {
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for element in list {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            }
+            Self::Compound(compound) => {
+                let mut map = serializer.serialize_map(Some(compound.len()))?;
+                for (name, value) in compound {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+            Self::IntArray(ints) => IntArray::new(ints.clone()).serialize(serializer),
+            Self::LongArray(longs) => LongArray::new(longs.clone()).serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+
+    #[test]
+    fn round_trip_every_tag() -> Result<()> {
+        let value = NbtValue::Compound(HashMap::from([
+            ("byte".to_string(), NbtValue::Byte(-1)),
+            ("short".to_string(), NbtValue::Short(300)),
+            ("int".to_string(), NbtValue::Int(70000)),
+            ("long".to_string(), NbtValue::Long(1 << 40)),
+            ("float".to_string(), NbtValue::Float(0.5)),
+            ("double".to_string(), NbtValue::Double(-2.25)),
+            ("bytes".to_string(), NbtValue::ByteArray(vec![1, -2, 3])),
+            ("string".to_string(), NbtValue::String("Minestodon".into())),
+            (
+                "list".to_string(),
+                NbtValue::List(vec![NbtValue::Int(1), NbtValue::Int(2)]),
+            ),
+            (
+                "compound".to_string(),
+                NbtValue::Compound(HashMap::from([(
+                    "nested".to_string(),
+                    NbtValue::String("yes".into()),
+                )])),
+            ),
+            ("ints".to_string(), NbtValue::IntArray(vec![i32::MIN, 0])),
+            ("longs".to_string(), NbtValue::LongArray(vec![i64::MAX])),
+        ]));
+
+        let mut buf = vec![];
+        buf.write_nbt(&value)?;
+        let mut slice = &buf[..];
+        assert_eq!(Some(value), slice.read_nbt_value()?);
+        assert!(slice.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn lone_end_tag_is_no_nbt() -> Result<()> {
+        let buf = [0x00, 0x0a, 0x00, 0x00, 0x00];
+        let mut slice = &buf[..];
+        assert_eq!(None, slice.read_nbt_value()?);
+        assert_eq!(4, slice.len());
+        Ok(())
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        // A root list of lists of lists...
+        let mut buf = vec![0x09, 0, 0];
+        for _ in 0..=NbtValue::MAX_DEPTH {
+            buf.extend([0x09, 0, 0, 0, 1]);
+        }
+        buf.extend([0x00, 0, 0, 0, 0]);
+        let err = (&buf[..]).read_nbt_value().unwrap_err();
+        assert!(format!("{err:?}").contains("nested deeper than 512 levels"));
+    }
+}
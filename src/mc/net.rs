@@ -1,9 +1,21 @@
+use crate::colored_text;
 use crate::mc::net::login::LoginDisconnect;
 use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt, PartialVarInt, VarInt};
-use crate::mc::net::pre_login::Listing;
+use crate::mc::net::play::chat::ChatSession;
+use crate::mc::net::play::container::{CloseContainerFromServer, SetContainerProperty};
+use crate::mc::net::play::entity::{EntityAttribute, SetPassengers, UpdateAttributes};
+use crate::mc::net::play::link::{ServerLink, ServerLinks};
+use crate::mc::net::play::resource_pack::ResourcePack;
+use crate::mc::net::play::server_data::ServerData;
+use crate::mc::net::play::world::{
+    ChunkBiomeData, ChunkBiomes, Respawn, SetBorderWarningDelay, SetBorderWarningDistance,
+    SetRenderDistance, SetSimulationDistance,
+};
+use crate::mc::net::play::{BundleDelimiter, KeepAliveFromServer, PlayDisconnect};
+use crate::mc::net::pre_login::{Handshake, Listing};
 use crate::mc::text::{NamedTextColor, Text};
+use crate::mc::Identifier;
 use crate::server::{ConnectionAction, Server};
-use crate::text;
 use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, WriteBytesExt};
 use flate2::read::ZlibDecoder;
@@ -12,107 +24,318 @@ use flate2::Compression;
 use log::{debug, warn};
 use std::collections::VecDeque;
 use std::fmt::Debug;
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 pub mod login;
 pub mod packet_io;
 pub mod play;
 pub mod pre_login;
+pub mod proxy;
+#[cfg(test)]
+pub mod test_util;
+
+pub const LOG_TARGET: &str = "minestodon::net";
+
+pub trait ConnectionIo: Read + Write + Send {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ConnectionIo for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+// Stands in for the stream of an async connection, so everything sent stays queued until the
+// async tick writes it out
+#[cfg(feature = "async")]
+struct QueuedIo;
+
+#[cfg(feature = "async")]
+impl Read for QueuedIo {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+}
+
+#[cfg(feature = "async")]
+impl Write for QueuedIo {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl ConnectionIo for QueuedIo {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 pub struct Connection {
-    pub stream: TcpStream,
+    pub stream: Box<dyn ConnectionIo>,
+    #[cfg(feature = "async")]
+    async_stream: Option<tokio::net::TcpStream>,
     pub uuid: Option<Uuid>,
 
     received_bytes: VecDeque<u8>,
-    packet: Option<PartialPacket>,
+    outbound: VecDeque<u8>,
 
+    awaiting_proxy_header: bool,
+    proxied_addr: Option<SocketAddr>,
     definitely_modern: bool,
+    handshake: Option<Handshake>,
     state: ConnectionState,
     pub compressed: bool,
+    compression_level: Compression,
+    compression_threshold: i32,
+    status_timeout: Duration,
+    max_packet_len: usize,
+    keep_alive_interval: Duration,
+    keep_alive_timeout: Duration,
+    keep_alive_due: Instant,
+    pending_keep_alive: Option<(i64, Instant)>,
+    resource_pack_forced: bool,
+    chat_session: Option<ChatSession>,
 }
 
 impl Connection {
     pub const COMPRESSION_THRESHOLD: i32 = 256;
+    pub const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+    pub const LEGACY_PING_TIMEOUT: Duration = Duration::from_millis(250);
+    pub const PLAY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+    pub const FINISH_WRITES_TIMEOUT: Duration = Duration::from_secs(1);
+    pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+    pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+    // Vanilla's limit, the most a 3-byte VarInt can hold
+    pub const MAX_PACKET_LEN: usize = (1 << 21) - 1;
+    pub const MAX_UNCOMPRESSED_LEN: usize = 1 << 23;
 
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: impl ConnectionIo + 'static) -> Self {
         Self {
-            stream,
+            stream: Box::new(stream),
+            #[cfg(feature = "async")]
+            async_stream: None,
             uuid: None,
             received_bytes: VecDeque::with_capacity(1024),
-            packet: None,
+            outbound: VecDeque::with_capacity(1024),
+            awaiting_proxy_header: false,
+            proxied_addr: None,
             definitely_modern: false,
+            handshake: None,
             state: ConnectionState::Handshake,
             compressed: false,
+            compression_level: Compression::default(),
+            compression_threshold: Self::COMPRESSION_THRESHOLD,
+            status_timeout: Self::STATUS_TIMEOUT,
+            max_packet_len: Self::MAX_PACKET_LEN,
+            keep_alive_interval: Self::KEEP_ALIVE_INTERVAL,
+            keep_alive_timeout: Self::KEEP_ALIVE_TIMEOUT,
+            keep_alive_due: Instant::now(),
+            pending_keep_alive: None,
+            resource_pack_forced: false,
+            chat_session: None,
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub fn new_async(stream: tokio::net::TcpStream) -> Self {
+        let mut connection = Self::new(QueuedIo);
+        connection.async_stream = Some(stream);
+        connection
+    }
+
+    pub fn tick(&mut self, server: &Server) -> Result<TickOutcome> {
+        self.flush_outbound()
+            .context("failed to send the queued data")?;
+        if let Some(outcome) = self.check_keep_alive() {
+            return Ok(outcome);
+        }
+
+        let outcome = self.receive(server)?;
+        self.finish_tick(outcome)
+    }
+
+    // Mirrors tick, but reads and writes through the async stream
+    #[cfg(feature = "async")]
+    pub async fn tick_async(&mut self, server: &Server) -> Result<TickOutcome> {
+        self.flush_outbound_async()
+            .await
+            .context("failed to send the queued data")?;
+        if let Some(outcome) = self.check_keep_alive() {
+            return Ok(outcome);
+        }
+
+        let outcome = self.receive_async(server).await?;
+        self.finish_tick(outcome)
+    }
+
+    fn check_keep_alive(&self) -> Option<TickOutcome> {
+        if self.state != ConnectionState::Play || !self.keep_alive_timed_out() {
+            return None;
+        }
+        debug!(target: LOG_TARGET, "Closing a connection that stopped answering keep-alives.");
+        let mut outcome = TickOutcome::new(0);
+        outcome.action = ConnectionAction::Close;
+        Some(outcome)
+    }
+
+    fn finish_tick(&mut self, outcome: TickOutcome) -> Result<TickOutcome> {
+        // Clients time out when the server is quiet, however much they send themselves
+        if self.state == ConnectionState::Play
+            && matches!(outcome.action, ConnectionAction::DoNothing)
+        {
+            self.send_keep_alive_if_due()?;
         }
+        Ok(outcome)
     }
 
-    pub fn tick(&mut self, server: &Server) -> Result<ConnectionAction> {
+    fn receive(&mut self, server: &Server) -> Result<TickOutcome> {
         let mut buf = [0; 1024];
-        let bytes_read = self
-            .stream
-            .read(&mut buf)
-            .context("failed to receive data from the client")?;
-        if bytes_read == 0 {
-            return Ok(ConnectionAction::Close);
-        }
-
-        let read = &buf[..bytes_read];
-        self.received_bytes.extend(read);
-        while let Some(byte) = self.received_bytes.pop_front() {
-            if !self.definitely_modern {
-                if byte == 0xfe {
-                    self.send_legacy_status_response(&read[1..], server.legacy_listing())
-                        .context("failed to send a legacy status response")?;
-                    return Ok(ConnectionAction::Close);
-                } else {
-                    self.definitely_modern = true;
-                }
+        let bytes_read = match self.stream.read(&mut buf) {
+            Err(err) if self.read_timeout().is_some() && is_timeout(&err) => {
+                return self.read_timed_out(server);
             }
+            read => read.context("failed to receive data from the client")?,
+        };
+        self.handle_received(&buf[..bytes_read], server)
+    }
 
-            let packet = self.packet.take().unwrap_or_else(PartialPacket::new);
-            match packet.next(byte)? {
-                PartialPacket::Full(body) => {
-                    let body = if self.compressed {
-                        let mut slice = &body[..];
-                        let len = slice
-                            .read_var::<i32>()
-                            .context("failed to read the uncompressed packet length")?
-                            .try_into()
-                            .context("the uncompressed packet length doesn't fit in a usize")?;
-
-                        if len != 0 {
-                            let mut decoder = ZlibDecoder::new(slice);
-                            let mut data = vec![0; len];
-                            decoder
-                                .read_exact(&mut data)
-                                .context("failed to decode the packet data")?;
-                            data
-                        } else {
-                            slice.to_vec()
-                        }
-                    } else {
-                        body
-                    };
-
-                    let mut slice = &body[..];
-                    let id = slice.read_var().context("failed to read the packet ID")?;
-                    let action = self.decode_and_handle_packet(id, &mut slice, server)?;
-                    let ConnectionAction::DoNothing = action else {
-                        return Ok(action);
-                    };
-                }
-                partial => self.packet = Some(partial),
+    #[cfg(feature = "async")]
+    async fn receive_async(&mut self, server: &Server) -> Result<TickOutcome> {
+        use tokio::io::AsyncReadExt;
+
+        let timeout = self.read_timeout();
+        let stream = self
+            .async_stream
+            .as_mut()
+            .context("the connection doesn't have an async stream")?;
+        let mut buf = [0; 1024];
+        let read = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, stream.read(&mut buf))
+                .await
+                .ok(),
+            None => Some(stream.read(&mut buf).await),
+        };
+        let Some(read) = read else {
+            return self.read_timed_out(server);
+        };
+        let bytes_read = read.context("failed to receive data from the client")?;
+        self.handle_received(&buf[..bytes_read], server)
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        match self.state {
+            ConnectionState::Status => Some(self.status_timeout),
+            ConnectionState::Play => Some(Self::PLAY_POLL_INTERVAL),
+            ConnectionState::Handshake if self.awaiting_legacy_ping() => {
+                Some(Self::LEGACY_PING_TIMEOUT)
+            }
+            _ => None,
+        }
+    }
+
+    fn update_read_timeout(&self) {
+        if let Err(err) = self.stream.set_read_timeout(self.read_timeout()) {
+            warn!(target: LOG_TARGET, "Failed to set the read timeout: {err:?}");
+        }
+    }
+
+    // Clients older than 1.6 send nothing after these bytes, so only silence tells them apart
+    // from a modern handshake that arrived in pieces
+    fn awaiting_legacy_ping(&self) -> bool {
+        !self.definitely_modern
+            && !self.awaiting_proxy_header
+            && is_legacy_ping_prefix(self.received_bytes.iter().copied())
+    }
+
+    fn read_timed_out(&mut self, server: &Server) -> Result<TickOutcome> {
+        let mut outcome = TickOutcome::new(0);
+        if self.state == ConnectionState::Handshake && self.awaiting_legacy_ping() {
+            let request: Vec<_> = self.received_bytes.drain(..).skip(1).collect();
+            self.send_legacy_status_response(&request, server.legacy_listing())
+                .context("failed to send a legacy status response")?;
+            outcome.action = ConnectionAction::Close;
+            return Ok(outcome);
+        }
+        // Status connections should have pinged by now, but players wake up regularly even
+        // when the client is quiet so queued commands get sent
+        if self.state == ConnectionState::Status {
+            debug!(target: LOG_TARGET, "Closing a status connection that never pinged.");
+            outcome.action = ConnectionAction::Close;
+        }
+        Ok(outcome)
+    }
+
+    fn handle_received(&mut self, received: &[u8], server: &Server) -> Result<TickOutcome> {
+        let mut outcome = TickOutcome::new(received.len());
+        if received.is_empty() {
+            outcome.action = ConnectionAction::Close;
+            return Ok(outcome);
+        }
+
+        self.received_bytes.extend(received);
+        if self.awaiting_proxy_header {
+            let pending = self.received_bytes.make_contiguous();
+            let Some((header, len)) = proxy::parse_header(pending)? else {
+                return Ok(outcome);
             };
+            debug!(target: LOG_TARGET, "Received a PROXY header for {:?}.", header.source);
+            self.received_bytes.drain(..len);
+            self.proxied_addr = header.source;
+            self.awaiting_proxy_header = false;
         }
-        Ok(ConnectionAction::DoNothing)
+
+        if !self.definitely_modern && !self.received_bytes.is_empty() {
+            let pending = self.received_bytes.make_contiguous();
+            if is_legacy_ping(pending) {
+                let request = pending[1..].to_vec();
+                self.send_legacy_status_response(&request, server.legacy_listing())
+                    .context("failed to send a legacy status response")?;
+                outcome.action = ConnectionAction::Close;
+                return Ok(outcome);
+            }
+            if self.awaiting_legacy_ping() {
+                self.update_read_timeout();
+                return Ok(outcome);
+            }
+            self.definitely_modern = true;
+            self.update_read_timeout();
+        }
+
+        while let Some(body) = take_frame(&mut self.received_bytes, self.max_packet_len)? {
+            let body = if self.compressed {
+                decompress(&body)?
+            } else {
+                body
+            };
+
+            let (id, mut slice) = peek_packet_id(&body)?;
+            let action = self.decode_and_handle_packet(id, &mut slice, server)?;
+            outcome.packets_handled += 1;
+            let ConnectionAction::DoNothing = action else {
+                outcome.action = action;
+                return Ok(outcome);
+            };
+        }
+        Ok(outcome)
     }
 
+    // Bodies are always fully buffered before decoding, so a truncated packet fails with an EOF
+    // instead of blocking the read helpers on the stream
     pub fn decode_and_handle_packet(
         &mut self,
         id: i32,
-        buf: &mut impl Read,
+        buf: &mut &[u8],
         server: &Server,
     ) -> Result<ConnectionAction> {
         let decoded = match self.state {
@@ -120,8 +343,13 @@ impl Connection {
             ConnectionState::Status => pre_login::decode_status(id, buf),
             ConnectionState::Login => login::decode(id, buf),
             ConnectionState::Play => {
-                warn!("Client-to-server play packets are not yet implemented! ({id:#04x})");
-                return Ok(ConnectionAction::DoNothing);
+                let decoded = play::decode(id, buf).context("failed to decode the packet")?;
+                let Some(decoded) = decoded else {
+                    // Play has far more packets than we handle, so skipping one isn't an error
+                    debug!(target: LOG_TARGET, "Skipping unhandled play packet {id:#04x}.");
+                    return Ok(ConnectionAction::DoNothing);
+                };
+                Ok(decoded)
             }
         };
         let decoded = decoded.context("failed to decode the packet")?;
@@ -131,12 +359,28 @@ impl Connection {
     }
 
     pub fn send_packet<P: PacketFromServer>(&mut self, packet: P) -> Result<()> {
-        let mut data_buf = Vec::with_capacity(1024);
+        let mut body = Vec::with_capacity(1024);
+        packet
+            .write(&mut body)
+            .context("failed to write the packet data")?;
+        self.send_raw(P::ID, &body)
+    }
+
+    pub fn send_dyn_packet(&mut self, packet: &dyn EncodablePacket) -> Result<()> {
+        let mut body = Vec::with_capacity(1024);
+        packet
+            .write_body(&mut body)
+            .context("failed to write the packet data")?;
+        self.send_raw(packet.id(), &body)
+    }
+
+    pub fn send_raw(&mut self, id: i32, body: &[u8]) -> Result<()> {
+        let mut data_buf = Vec::with_capacity(i32::MAX_VAR_LEN + body.len());
         data_buf
-            .write_var(P::id())
+            .write_var(id)
             .context("failed to write the packet ID")?;
-        packet
-            .write(&mut data_buf)
+        data_buf
+            .write_all(body)
             .context("failed to write the packet data")?;
 
         let data_len = data_buf
@@ -146,11 +390,11 @@ impl Connection {
 
         let (len, buf) = if self.compressed {
             let mut buf = Vec::with_capacity(1024 + i32::MAX_VAR_LEN);
-            if data_len >= Self::COMPRESSION_THRESHOLD {
+            if data_len >= self.compression_threshold {
                 buf.write_var(data_len)
                     .context("failed to write the uncompressed packet length")?;
 
-                let mut encoder = ZlibEncoder::new(buf, Compression::default());
+                let mut encoder = ZlibEncoder::new(buf, self.compression_level);
                 encoder
                     .write_all(&data_buf)
                     .context("failed to encode and write the packet data")?;
@@ -173,18 +417,301 @@ impl Connection {
             (data_len, data_buf)
         };
 
-        self.stream
-            .write_var::<i32>(len)
-            .context("failed to send the packet length")?;
-        self.stream
-            .write_all(&buf)
-            .context("failed to send the packet body")
+        let mut prefix = [0; i32::MAX_VAR_LEN];
+        let prefix_len = packet_io::write_var_into::<i32>(&mut prefix, len);
+        self.outbound.reserve(prefix_len + buf.len());
+        self.outbound.extend(&prefix[..prefix_len]);
+        self.outbound.extend(buf);
+        self.flush_outbound().context("failed to send the packet")
+    }
+
+    // Whatever the stream doesn't accept yet stays queued so the next packet can't be written
+    // into the middle of this one
+    pub fn flush_outbound(&mut self) -> Result<()> {
+        while !self.outbound.is_empty() {
+            let (pending, _) = self.outbound.as_slices();
+            match self.stream.write(pending) {
+                Ok(0) => bail!("the client stopped accepting data"),
+                Ok(written) => drop(self.outbound.drain(..written)),
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err).context("failed to write the queued data"),
+            }
+        }
+
+        match self.stream.flush() {
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            result => result.context("failed to flush the stream"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn flush_outbound_async(&mut self) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let stream = self
+            .async_stream
+            .as_mut()
+            .context("the connection doesn't have an async stream")?;
+        while !self.outbound.is_empty() {
+            let (pending, _) = self.outbound.as_slices();
+            let written = stream
+                .write(pending)
+                .await
+                .context("failed to write the queued data")?;
+            if written == 0 {
+                bail!("the client stopped accepting data");
+            }
+            self.outbound.drain(..written);
+        }
+        stream.flush().await.context("failed to flush the stream")
+    }
+
+    // Blocks until everything queued has been written, since the connection is about to close
+    pub fn finish_writes(&mut self) -> Result<()> {
+        // Async connections can't block here; their task finishes writing before closing
+        #[cfg(feature = "async")]
+        if self.async_stream.is_some() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + Self::FINISH_WRITES_TIMEOUT;
+        loop {
+            self.flush_outbound()?;
+            if !self.has_pending_writes() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "timed out with {} bytes still waiting to be written",
+                    self.outbound.len()
+                );
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn finish_writes_async(&mut self) -> Result<()> {
+        let pending = self.outbound.len();
+        tokio::time::timeout(Self::FINISH_WRITES_TIMEOUT, self.flush_outbound_async())
+            .await
+            .with_context(|| format!("timed out with {pending} bytes waiting to be written"))?
+    }
+
+    pub fn has_pending_writes(&self) -> bool {
+        !self.outbound.is_empty()
+    }
+
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.compression_level = Compression::new(level.min(9));
+    }
+
+    pub fn compression_enabled(&self) -> bool {
+        self.compressed
+    }
+
+    pub fn compression_threshold(&self) -> i32 {
+        self.compression_threshold
+    }
+
+    pub fn set_compression_threshold(&mut self, threshold: i32) {
+        self.compression_threshold = threshold;
+    }
+
+    // Proxies using the PROXY protocol send a header with the real client address first
+    pub fn expect_proxy_header(&mut self) {
+        self.awaiting_proxy_header = true;
+    }
+
+    pub fn proxied_addr(&self) -> Option<SocketAddr> {
+        self.proxied_addr
+    }
+
+    fn keep_alive_timed_out(&self) -> bool {
+        self.pending_keep_alive
+            .is_some_and(|(_, sent_at)| sent_at.elapsed() >= self.keep_alive_timeout)
+    }
+
+    fn send_keep_alive_if_due(&mut self) -> Result<()> {
+        let now = Instant::now();
+        if self.pending_keep_alive.is_some() || now < self.keep_alive_due {
+            return Ok(());
+        }
+
+        // Like vanilla, the ID is a timestamp in milliseconds
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        self.send_packet(KeepAliveFromServer { id })
+            .context("failed to send a keep-alive")?;
+        self.pending_keep_alive = Some((id, now));
+        self.keep_alive_due = now + self.keep_alive_interval;
+        Ok(())
+    }
+
+    fn answer_keep_alive(&mut self, id: i64) {
+        match self.pending_keep_alive {
+            Some((pending, _)) if pending == id => self.pending_keep_alive = None,
+            _ => debug!(target: LOG_TARGET, "Ignoring an answer to unknown keep-alive {id}."),
+        }
+    }
+
+    pub fn set_status_timeout(&mut self, timeout: Duration) {
+        self.status_timeout = timeout;
+    }
+
+    pub fn set_max_packet_len(&mut self, len: usize) {
+        self.max_packet_len = len;
+    }
+
+    pub fn send_resource_pack(&mut self, packet: ResourcePack) -> Result<()> {
+        let forced = packet.forced;
+        self.send_packet(packet)
+            .context("failed to send the resource pack packet")?;
+        self.resource_pack_forced = forced;
+        Ok(())
+    }
+
+    pub fn bundle<R>(&mut self, send: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        let supported = self
+            .protocol_version()
+            .is_some_and(|version| version >= BundleDelimiter::MIN_PROTOCOL_VERSION);
+        if !supported {
+            return send(self);
+        }
+
+        self.send_packet(BundleDelimiter)
+            .context("failed to start the bundle")?;
+        let result = send(self)?;
+        self.send_packet(BundleDelimiter)
+            .context("failed to end the bundle")?;
+        Ok(result)
+    }
+
+    pub fn set_movement_speed(&mut self, entity_id: i32, speed: f64) -> Result<()> {
+        self.send_packet(UpdateAttributes {
+            entity_id,
+            attributes: vec![EntityAttribute::movement_speed(speed)],
+        })
+        .context("failed to send the update attributes packet")
+    }
+
+    pub fn set_passengers(&mut self, vehicle: i32, passengers: &[i32]) -> Result<()> {
+        self.send_packet(SetPassengers {
+            vehicle,
+            passengers,
+        })
+        .context("failed to send the set passengers packet")
+    }
+
+    pub fn close_container(&mut self, window_id: u8) -> Result<()> {
+        self.send_packet(CloseContainerFromServer { window_id })
+            .context("failed to send the close container packet")
+    }
+
+    pub fn set_container_property(
+        &mut self,
+        window_id: u8,
+        property: i16,
+        value: i16,
+    ) -> Result<()> {
+        let packet = SetContainerProperty {
+            window_id,
+            property,
+            value,
+        };
+        self.send_packet(packet)
+            .context("failed to send the container property packet")
+    }
+
+    pub fn send_server_links(&mut self, links: &[ServerLink]) -> Result<()> {
+        let supported = self
+            .protocol_version()
+            .is_some_and(|version| version >= ServerLinks::MIN_PROTOCOL_VERSION);
+        if !supported {
+            debug!(target: LOG_TARGET, "Not sending server links to a client that doesn't support them.");
+            return Ok(());
+        }
+
+        self.send_packet(ServerLinks { links })
+            .context("failed to send the server links packet")
+    }
+
+    pub fn set_border_warning_delay(&mut self, seconds: i32) -> Result<()> {
+        self.send_packet(SetBorderWarningDelay { seconds })
+            .context("failed to send the border warning delay packet")
+    }
+
+    pub fn set_border_warning_distance(&mut self, blocks: i32) -> Result<()> {
+        self.send_packet(SetBorderWarningDistance { blocks })
+            .context("failed to send the border warning distance packet")
+    }
+
+    pub fn set_render_distance(&mut self, chunks: i32) -> Result<()> {
+        self.send_packet(SetRenderDistance { chunks })
+            .context("failed to send the render distance packet")
+    }
+
+    pub fn set_simulation_distance(&mut self, chunks: i32) -> Result<()> {
+        self.send_packet(SetSimulationDistance { chunks })
+            .context("failed to send the simulation distance packet")
+    }
+
+    pub fn send_chunk_biomes(&mut self, chunks: Vec<ChunkBiomeData>) -> Result<()> {
+        let supported = self
+            .protocol_version()
+            .is_some_and(|version| version >= ChunkBiomes::MIN_PROTOCOL_VERSION);
+        if !supported {
+            debug!(target: LOG_TARGET, "Not sending chunk biomes to a client that doesn't support them.");
+            return Ok(());
+        }
+
+        self.send_packet(ChunkBiomes { chunks })
+            .context("failed to send the chunk biomes packet")
+    }
+
+    pub fn send_server_data(
+        &mut self,
+        motd: Option<&Text>,
+        icon: Option<&str>,
+        enforces_secure_chat: bool,
+    ) -> Result<()> {
+        let data = ServerData {
+            motd,
+            icon,
+            enforces_secure_chat,
+            protocol_version: self.protocol_version().unwrap_or_default(),
+        };
+        self.send_packet(data)
+            .context("failed to send the server data packet")
+    }
+
+    pub fn switch_world(
+        &mut self,
+        world: Identifier,
+        dimension_type: Identifier,
+        server: &Server,
+    ) -> Result<()> {
+        let respawn = Respawn {
+            dimension_type,
+            world,
+            hashed_seed: 0,
+            game_mode: server.config().game_mode,
+            last_game_mode: server.config().last_game_mode,
+            debug_mode: server.config().debug_mode,
+            flat_world: server.config().flat_world,
+            copy_metadata: true,
+            death_pos: None,
+        };
+        self.send_packet(respawn)
+            .context("failed to send the respawn packet")
     }
 
     pub fn send_legacy_status_response(&mut self, request: &[u8], listing: Listing) -> Result<()> {
         let response = if request.is_empty() {
             // <1.4
-            debug!("Sending a legacy (<1.4) status response.");
+            debug!(target: LOG_TARGET, "Sending a legacy (<1.4) status response.");
             format!(
                 "{}\u{00a7}{}\u{00a7}{}",
                 listing.motd.to_plain_string(),
@@ -193,7 +720,7 @@ impl Connection {
             )
         } else {
             // 1.4-1.6
-            debug!("Sending a legacy (1.4-1.6) status response.");
+            debug!(target: LOG_TARGET, "Sending a legacy (1.4-1.6) status response.");
             format!(
                 "\u{00a7}1\0{}\0{}\0{}\0{}\0{}",
                 listing.version.value,
@@ -214,55 +741,187 @@ impl Connection {
             .flat_map(u16::to_be_bytes)
             .collect::<Vec<u8>>();
 
-        self.stream
-            .write_u8(0xff)
-            .context("failed to send the packet ID")?;
-        self.stream
-            .write_u16::<BigEndian>(len)
-            .context("failed to send the response length")?;
-        self.stream
-            .write_all(&bytes)
+        let mut buf = Vec::with_capacity(3 + bytes.len());
+        buf.write_u8(0xff)
+            .context("failed to write the packet ID")?;
+        buf.write_u16::<BigEndian>(len)
+            .context("failed to write the response length")?;
+        buf.write_all(&bytes)
+            .context("failed to write the response")?;
+        self.outbound.extend(buf);
+        self.finish_writes()
             .context("failed to send the response")?;
 
         Ok(())
     }
 
     pub fn send_kick(&mut self, reason: Text) -> Result<()> {
+        let json_len = reason.json_len()?;
+        if json_len > Text::MAX_JSON_LEN {
+            warn!(target: LOG_TARGET, "The kick reason is {json_len} characters long, so the client will likely reject it.");
+        }
         match self.state {
             ConnectionState::Login => {
                 let packet = LoginDisconnect { reason };
                 self.send_packet(packet)
                     .context("failed to send a login disconnect packet")?;
             }
-            ConnectionState::Play => todo!(),
+            ConnectionState::Play => {
+                let packet = PlayDisconnect { reason };
+                self.send_packet(packet)
+                    .context("failed to send a play disconnect packet")?;
+            }
             _ => bail!("the current state doesn't support kicking"),
         }
         Ok(())
     }
 
     pub fn send_error_kick(&mut self, error: impl Debug) -> Result<()> {
-        let error = format!("{error:?}")
-            .lines()
-            .map(str::trim)
-            .collect::<Vec<_>>()
-            .join("\n");
-        let reason = Text::from("Minestodon Error\n\n")
-            .color(NamedTextColor::Red)
-            .underlined(true)
-            .push_sequential(Text::from(error).color(NamedTextColor::Gray))
-            .push_sequential(
-                text!(
-                    "\n\nThis is probably not your fault! Please report it here:\n{}",
-                    crate::ISSUE_URL
-                )
-                .color(NamedTextColor::Gold),
-            );
-        self.send_kick(reason)
-    }
-
-    pub fn set_state(&mut self, state: ConnectionState) {
-        debug!("State change: {:?} -> {state:?}", self.state);
+        self.send_kick(error_kick_reason(error))
+    }
+
+    pub fn chat_session(&self) -> Option<&ChatSession> {
+        self.chat_session.as_ref()
+    }
+
+    pub fn handshake(&self) -> Option<&Handshake> {
+        self.handshake.as_ref()
+    }
+
+    pub fn protocol_version(&self) -> Option<i32> {
+        self.handshake.as_ref().map(|handshake| handshake.version)
+    }
+
+    pub fn set_state(&mut self, state: ConnectionState) -> Result<()> {
+        if !self.state.can_switch_to(&state) {
+            bail!("illegal state change: {:?} -> {state:?}", self.state);
+        }
+        debug!(target: LOG_TARGET, "State change: {:?} -> {state:?}", self.state);
         self.state = state;
+        self.update_read_timeout();
+        if self.state == ConnectionState::Play {
+            self.keep_alive_due = Instant::now() + self.keep_alive_interval;
+        }
+        Ok(())
+    }
+}
+
+pub fn peek_packet_id(body: &[u8]) -> Result<(i32, &[u8])> {
+    let mut slice = body;
+    let id = slice.read_var().context("failed to read the packet ID")?;
+    Ok((id, slice))
+}
+
+fn decompress(body: &[u8]) -> Result<Vec<u8>> {
+    let mut slice = body;
+    let len = slice
+        .read_len()
+        .context("failed to read the uncompressed packet length")?;
+    if len == 0 {
+        return Ok(slice.to_vec());
+    }
+    if len > Connection::MAX_UNCOMPRESSED_LEN {
+        bail!(
+            "the packet decompresses to {len} bytes, but the limit is {}",
+            Connection::MAX_UNCOMPRESSED_LEN
+        );
+    }
+
+    let start = &slice[..slice.len().min(8)];
+    let mut data = Vec::with_capacity(len);
+    // Reading one byte past the declared length is enough to notice an oversized packet
+    let mut decoder = ZlibDecoder::new(slice).take(len as u64 + 1);
+    let decoded = decoder.read_to_end(&mut data);
+    decoded.with_context(|| {
+        format!(
+            "failed to decode the packet data after {} of {len} declared bytes \
+             (compressed data starts with {start:02x?})",
+            data.len()
+        )
+    })?;
+    if data.len() != len {
+        bail!(
+            "the packet data decompressed to {}{} bytes, but {len} were declared \
+             (compressed data starts with {start:02x?})",
+            if data.len() > len { "more than " } else { "" },
+            data.len().min(len),
+        );
+    }
+    Ok(data)
+}
+
+const MAX_ERROR_KICK_CHARS: usize = 1024;
+
+fn error_kick_reason(error: impl Debug) -> Text {
+    let mut error = format!("{error:?}")
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Some((end, _)) = error.char_indices().nth(MAX_ERROR_KICK_CHARS) {
+        error.truncate(end);
+        error.push('\u{2026}');
+    }
+    let report = Text::from("Report this")
+        .color(NamedTextColor::Gold)
+        .underlined(true)
+        .click_open_url(crate::ISSUE_URL)
+        .hover_show_text("Opens the issue tracker");
+    Text::from("Minestodon Error\n\n")
+        .color(NamedTextColor::Red)
+        .underlined(true)
+        .push_sequential(Text::from(error).color(NamedTextColor::Gray))
+        .push_sequential(colored_text!(
+            NamedTextColor::Gold,
+            "\n\nThis is probably not your fault! "
+        ))
+        .push_sequential(report)
+}
+
+// The client going away mid-write means there's nobody left to show a kick to
+pub fn is_disconnect(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| {
+            matches!(
+                err.kind(),
+                ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+            )
+        })
+}
+
+// A modern handshake that's 254 bytes long also starts with 0xfe 0x01, but its packet ID follows
+// instead of the 0xfa that 1.6 clients send
+fn is_legacy_ping(read: &[u8]) -> bool {
+    matches!(read, [0xfe, 0x01, 0xfa, ..])
+}
+
+fn is_legacy_ping_prefix(read: impl Iterator<Item = u8>) -> bool {
+    let read: Vec<_> = read.take(3).collect();
+    matches!(read[..], [0xfe] | [0xfe, 0x01])
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+pub struct TickOutcome {
+    pub bytes_read: usize,
+    pub packets_handled: usize,
+    pub action: ConnectionAction,
+}
+
+impl TickOutcome {
+    pub fn new(bytes_read: usize) -> Self {
+        Self {
+            bytes_read,
+            packets_handled: 0,
+            action: ConnectionAction::DoNothing,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.packets_handled == 0
     }
 }
 
@@ -274,61 +933,94 @@ pub enum ConnectionState {
     Play,
 }
 
-enum PartialPacket {
-    AwaitingLen(PartialVarInt<i32>),
-    AwaitingBody { len: usize, body: Vec<u8> },
-    Full(Vec<u8>),
+impl ConnectionState {
+    pub fn can_switch_to(&self, next: &ConnectionState) -> bool {
+        matches!(
+            (self, next),
+            (Self::Handshake, Self::Status | Self::Login) | (Self::Login, Self::Play)
+        )
+    }
 }
 
-impl PartialPacket {
-    pub fn new() -> Self {
-        Self::AwaitingLen(PartialVarInt::new())
-    }
-
-    pub fn next(self, byte: u8) -> Result<Self> {
-        let next = match self {
-            Self::AwaitingLen(len) => {
-                let next = len
-                    .next(byte)
-                    .context("received an invalid byte while awaiting the packet length")?;
-                match next {
-                    PartialVarInt::Full(len) => {
-                        let len = len
-                            .try_into()
-                            .context("the packet length doesn't fit in a usize")?;
-                        Self::AwaitingBody { len, body: vec![] }
-                    }
-                    partial => Self::AwaitingLen(partial),
-                }
-            }
-            Self::AwaitingBody { len, mut body } => {
-                body.push(byte);
-                if body.len() == len {
-                    Self::Full(body)
-                } else {
-                    Self::AwaitingBody { len, body }
-                }
-            }
-            full => full,
+// Frames are only taken once they've fully arrived, so partial ones wait in the buffer
+fn take_frame(received: &mut VecDeque<u8>, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let mut partial_len = PartialVarInt::<i32>::new();
+    let mut header_len = 0;
+    let len = loop {
+        let Some(&byte) = received.get(header_len) else {
+            return Ok(None);
         };
-        Ok(next)
+        header_len += 1;
+        match partial_len
+            .next(byte)
+            .context("received an invalid byte while awaiting the packet length")?
+        {
+            PartialVarInt::Full(len) => break len,
+            partial => partial_len = partial,
+        }
+    };
+    if len < 0 {
+        bail!("the packet length is negative ({len})");
+    }
+    let len: usize = len
+        .try_into()
+        .context("the packet length doesn't fit in a usize")?;
+    if len > max_len {
+        bail!("the packet is {len} bytes long, but the limit is {max_len}");
+    }
+    if received.len() - header_len < len {
+        return Ok(None);
     }
+
+    received.drain(..header_len);
+    Ok(Some(received.drain(..len).collect()))
 }
 
 pub trait PacketFromServer {
-    fn id() -> i32;
+    const ID: i32;
+
+    fn id() -> i32 {
+        Self::ID
+    }
+
     fn write<W: Write>(&self, buf: &mut W) -> Result<()>;
 }
 
-pub trait PacketFromClient {
-    fn id() -> i32
-    where
-        Self: Sized;
+// An object-safe PacketFromServer, for packets that are queued for other threads to send
+pub trait EncodablePacket: Send {
+    fn id(&self) -> i32;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<()>;
+}
 
-    fn read<R: Read>(buf: &mut R) -> Result<Self>
-    where
-        Self: Sized;
+impl<P: PacketFromServer + Send> EncodablePacket for P {
+    fn id(&self) -> i32 {
+        P::ID
+    }
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.write(buf)
+    }
+}
+
+pub trait PacketFromClient: Sized {
+    const ID: i32;
+
+    fn id() -> i32 {
+        Self::ID
+    }
+
+    fn read<R: Read>(buf: &mut R) -> Result<Self>;
+
+    fn handle(
+        self: Box<Self>,
+        connection: &mut Connection,
+        server: &Server,
+    ) -> Result<ConnectionAction>;
+}
 
+// Associated consts rule out trait objects, so decoded packets are boxed as this instead
+pub trait DecodedPacket {
     fn handle(
         self: Box<Self>,
         connection: &mut Connection,
@@ -336,6 +1028,16 @@ pub trait PacketFromClient {
     ) -> Result<ConnectionAction>;
 }
 
+impl<P: PacketFromClient> DecodedPacket for P {
+    fn handle(
+        self: Box<Self>,
+        connection: &mut Connection,
+        server: &Server,
+    ) -> Result<ConnectionAction> {
+        PacketFromClient::handle(self, connection, server)
+    }
+}
+
 #[macro_export]
 macro_rules! packets_from_client {
     ($fn_name:ident, $state:expr, [$($packet:ident),* $(,)?] $(,)?) => {
@@ -343,14 +1045,699 @@ macro_rules! packets_from_client {
         pub fn $fn_name(
             id: i32,
             buf: &mut impl ::std::io::Read,
-        ) -> ::anyhow::Result<::std::boxed::Box<dyn $crate::mc::net::PacketFromClient>> {
-            let packet: ::std::boxed::Box<dyn $crate::mc::net::PacketFromClient> = match id {
+        ) -> ::anyhow::Result<::std::boxed::Box<dyn $crate::mc::net::DecodedPacket>> {
+            let packet: ::std::boxed::Box<dyn $crate::mc::net::DecodedPacket> = match id {
                 $(
-                    id if id == $packet::id() => ::std::boxed::Box::new($packet::read(buf)?),
+                    $packet::ID => ::std::boxed::Box::new($packet::read(buf)?),
                 )*
                 id => ::anyhow::bail!(::std::concat!("invalid ", $state, " packet ID {:#04x}"), id),
             };
             ::std::result::Result::Ok(packet)
         }
     };
+    ($fn_name:ident, [$($packet:ident),* $(,)?], skip_unknown $(,)?) => {
+        #[allow(unreachable_code, unused_variables)]
+        pub fn $fn_name(
+            id: i32,
+            buf: &mut impl ::std::io::Read,
+        ) -> ::anyhow::Result<
+            ::std::option::Option<::std::boxed::Box<dyn $crate::mc::net::DecodedPacket>>,
+        > {
+            let packet: ::std::boxed::Box<dyn $crate::mc::net::DecodedPacket> = match id {
+                $(
+                    $packet::ID => ::std::boxed::Box::new($packet::read(buf)?),
+                )*
+                _ => return ::std::result::Result::Ok(::std::option::Option::None),
+            };
+            ::std::result::Result::Ok(::std::option::Option::Some(packet))
+        }
+    };
+}
+
+// Declares simple Play packets and their handlers in one line each, along with a skip_unknown
+// decoder for them
+#[macro_export]
+macro_rules! play_packets {
+    (
+        $fn_name:ident,
+        [
+            $(
+                $packet:ident($id:literal) { $($field:ident: $ty:ty),* $(,)? } => $handler:expr
+            ),* $(,)?
+        ] $(,)?
+    ) => {
+        $(
+            #[derive(Clone, Debug)]
+            pub struct $packet {
+                $(pub $field: $ty,)*
+            }
+
+            impl $crate::mc::net::PacketFromClient for $packet {
+                const ID: i32 = $id;
+
+                fn read<R: ::std::io::Read>(buf: &mut R) -> ::anyhow::Result<Self> {
+                    $(
+                        let $field = <$ty as $crate::mc::net::packet_io::PacketField>::read_field(buf)
+                            .map_err(|err| err.context(::std::concat!(
+                                "failed to read the ",
+                                ::std::stringify!($field),
+                            )))?;
+                    )*
+                    ::std::result::Result::Ok(Self { $($field,)* })
+                }
+
+                fn handle(
+                    self: ::std::boxed::Box<Self>,
+                    connection: &mut $crate::mc::net::Connection,
+                    server: &$crate::server::Server,
+                ) -> ::anyhow::Result<$crate::server::ConnectionAction> {
+                    let handler: fn(
+                        Self,
+                        &mut $crate::mc::net::Connection,
+                        &$crate::server::Server,
+                    ) -> ::anyhow::Result<$crate::server::ConnectionAction> = $handler;
+                    handler(*self, connection, server)
+                }
+            }
+        )*
+
+        $crate::packets_from_client!($fn_name, [$($packet),*], skip_unknown);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::pre_login::{
+        NextState, PingRequest, PingResponse, StatusRequest, StatusResponse,
+    };
+    use crate::mc::net::test_util;
+    use crate::mc::player::GameMode;
+    use crate::mc::text::JsonStringType;
+    use crate::mc::world;
+    use crate::server::ServerConfig;
+    use byteorder::ReadBytesExt;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn tick_counts_packets_in_one_read() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        handshake.write_u16::<BigEndian>(25565)?;
+        handshake.write_var(1)?;
+        let mut buf = vec![];
+        test_util::write_packet(&mut buf, 0x00, &handshake, false)?;
+        test_util::write_packet(&mut buf, StatusRequest::ID, &[], false)?;
+        client.write_all(&buf)?;
+
+        let outcome = connection.tick(&server)?;
+        assert_eq!(buf.len(), outcome.bytes_read);
+        assert_eq!(2, outcome.packets_handled);
+        assert!(!outcome.is_idle());
+        assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_frames_wait_for_the_next_read() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        handshake.write_u16::<BigEndian>(25565)?;
+        handshake.write_var(1)?;
+        let mut buf = vec![];
+        test_util::write_packet(&mut buf, 0x00, &handshake, false)?;
+        test_util::write_packet(&mut buf, StatusRequest::ID, &[], false)?;
+        let mut ping = vec![];
+        test_util::write_packet(&mut ping, PingRequest::ID, &42_i64.to_be_bytes(), false)?;
+        buf.extend(&ping[..5]);
+        client.write_all(&buf)?;
+
+        let outcome = connection.tick(&server)?;
+        assert_eq!(buf.len(), outcome.bytes_read);
+        assert_eq!(2, outcome.packets_handled);
+        assert_eq!(ConnectionState::Status, connection.state);
+        assert_eq!(ping[..5], *connection.received_bytes.make_contiguous());
+
+        client.write_all(&ping[5..])?;
+        let outcome = connection.tick(&server)?;
+        assert_eq!(1, outcome.packets_handled);
+        assert!(connection.received_bytes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn keep_alives_are_sent_and_answered() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::play_connection();
+        connection.keep_alive_due = Instant::now();
+
+        connection.tick(&server)?;
+        let (id, body) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(KeepAliveFromServer::ID, id);
+        let keep_alive = i64::from_be_bytes(body[..].try_into()?);
+
+        test_util::write_packet(&mut client, 0x11, &keep_alive.to_be_bytes(), false)?;
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+        assert_eq!(None, connection.pending_keep_alive);
+        Ok(())
+    }
+
+    #[test]
+    fn busy_clients_still_get_keep_alives() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::play_connection();
+
+        // 20 simulated seconds of a client sending something every poll
+        for _ in 0..400 {
+            test_util::write_packet(&mut client, 0x00, &[0x01], false)?;
+            let outcome = connection.tick(&server)?;
+            assert!(!outcome.is_idle());
+            assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+            if let Some(due) = connection
+                .keep_alive_due
+                .checked_sub(Connection::PLAY_POLL_INTERVAL)
+            {
+                connection.keep_alive_due = due;
+            }
+        }
+        assert!(connection.pending_keep_alive.is_some());
+
+        let (id, body) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(KeepAliveFromServer::ID, id);
+        test_util::write_packet(&mut client, 0x11, &body, false)?;
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+        assert_eq!(None, connection.pending_keep_alive);
+        Ok(())
+    }
+
+    #[test]
+    fn unanswered_keep_alives_close_the_connection() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::play_connection();
+        connection.keep_alive_interval = Duration::ZERO;
+        connection.keep_alive_due = Instant::now();
+        connection.keep_alive_timeout = Duration::ZERO;
+
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::Close));
+        Ok(())
+    }
+
+    #[test]
+    fn switch_world_uses_configured_game_mode() -> Result<()> {
+        let config = ServerConfig {
+            game_mode: GameMode::Creative,
+            last_game_mode: Some(GameMode::Survival),
+            flat_world: false,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let (mut connection, mut client) = test_util::play_connection();
+        let profile = world::PROFILE_WORLD;
+        connection.switch_world(profile.id.clone(), profile.dimension_type.clone(), &server)?;
+
+        let (id, body) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(Respawn::ID, id);
+        let mut body = &body[..];
+        assert_eq!(profile.dimension_type, body.read_identifier()?);
+        assert_eq!(profile.id, body.read_identifier()?);
+        assert_eq!(0, body.read_i64::<BigEndian>()?);
+        assert_eq!(i8::from(GameMode::Creative), body.read_i8()?);
+        assert_eq!(i8::from(GameMode::Survival), body.read_i8()?);
+        assert!(!body.read_bool()?);
+        assert!(!body.read_bool()?);
+        assert!(body.read_bool()?);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_play_packets_are_skipped() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::play_connection();
+        let mut body = &[0x01, 0x02][..];
+        let action = connection.decode_and_handle_packet(0x7f, &mut body, &server)?;
+        assert!(matches!(action, ConnectionAction::DoNothing));
+
+        let mut message = vec![];
+        message.write_str("minecraft:brand")?;
+        message.write_str("vanilla")?;
+        let action = connection.decode_and_handle_packet(0x0c, &mut &message[..], &server)?;
+        assert!(matches!(action, ConnectionAction::DoNothing));
+        Ok(())
+    }
+
+    #[test]
+    fn play_error_kick_is_well_formed() -> Result<()> {
+        let (mut connection, mut client) = test_util::play_connection();
+        connection.send_error_kick("the world fell over")?;
+        connection.finish_writes()?;
+
+        let (id, body) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(PlayDisconnect::ID, id);
+        let mut body = &body[..];
+        let reason = serde_json::from_str::<Text>(&body.read_string()?)?;
+        assert!(reason.to_plain_string().contains("the world fell over"));
+        assert!(body.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn frames_are_taken_whole() -> Result<()> {
+        let mut buf = vec![];
+        test_util::write_packet(&mut buf, 0x01, &[1; 200], false)?;
+        test_util::write_packet(&mut buf, 0x02, &[], false)?;
+        buf.extend([0x05, 0x03]);
+        let mut received = VecDeque::from(buf);
+
+        let first = take_frame(&mut received, Connection::MAX_PACKET_LEN)?
+            .context("the first frame wasn't taken")?;
+        assert_eq!(201, first.len());
+        assert_eq!(
+            Some(vec![0x02]),
+            take_frame(&mut received, Connection::MAX_PACKET_LEN)?
+        );
+        assert_eq!(None, take_frame(&mut received, Connection::MAX_PACKET_LEN)?);
+        assert_eq!(2, received.len());
+        Ok(())
+    }
+
+    #[test]
+    fn huge_length_prefixes_are_rejected() -> Result<()> {
+        let mut received = VecDeque::new();
+        received.write_var(i32::MAX)?;
+        received.push_back(0x00);
+        let err = take_frame(&mut received, Connection::MAX_PACKET_LEN).unwrap_err();
+        assert!(err.to_string().contains("the limit is 2097151"));
+
+        let mut body = vec![];
+        body.write_var(i32::MAX)?;
+        assert!(decompress(&body).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_body_fails_without_blocking() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::connection();
+
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        let result = connection.decode_and_handle_packet(0x00, &mut &handshake[..], &server);
+        let err = result
+            .err()
+            .context("the truncated handshake was accepted")?;
+        let eof = err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<io::Error>())
+            .any(|err| err.kind() == ErrorKind::UnexpectedEof);
+        assert!(eof, "the error wasn't caused by an EOF: {err:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn peek_packet_id_leaves_the_body() -> Result<()> {
+        let mut body = vec![];
+        body.write_var(0x2e)?;
+        body.extend([1, 2, 3]);
+        let (id, rest) = peek_packet_id(&body)?;
+        assert_eq!(0x2e, id);
+        assert_eq!([1, 2, 3], rest);
+        assert!(peek_packet_id(&[0x80]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn long_handshake_is_not_a_legacy_ping() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str(&"a".repeat(246))?;
+        handshake.write_u16::<BigEndian>(25565)?;
+        handshake.write_var(1)?;
+        test_util::write_packet(&mut client, 0x00, &handshake, false)?;
+
+        let outcome = connection.tick(&server)?;
+        assert_eq!(1, outcome.packets_handled);
+        assert_eq!(ConnectionState::Status, connection.state);
+        assert_eq!(Some(761), connection.protocol_version());
+        assert!(is_legacy_ping(&[0xfe, 0x01, 0xfa, 0x00, 0x0b]));
+        assert!(!is_legacy_ping(&[0xfe, 0x01, 0x00]));
+        Ok(())
+    }
+
+    #[test]
+    fn split_long_handshake_is_not_a_legacy_ping() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str(&"a".repeat(246))?;
+        handshake.write_u16::<BigEndian>(25565)?;
+        handshake.write_var(1)?;
+        let mut frame = vec![];
+        test_util::write_packet(&mut frame, 0x00, &handshake, false)?;
+        assert_eq!([0xfe, 0x01], frame[..2]);
+
+        for byte in &frame[..2] {
+            client.write_all(&[*byte])?;
+            let outcome = connection.tick(&server)?;
+            assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+            assert_eq!(0, outcome.packets_handled);
+        }
+        client.write_all(&frame[2..])?;
+
+        let outcome = connection.tick(&server)?;
+        assert_eq!(1, outcome.packets_handled);
+        assert_eq!(ConnectionState::Status, connection.state);
+        assert_eq!(Some(761), connection.protocol_version());
+        Ok(())
+    }
+
+    #[test]
+    fn proxy_v1_header_is_consumed() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+        connection.expect_proxy_header();
+
+        client.write_all(b"PROXY TCP4 198.51.100.4 10.0.0.1 51234 25565\r\n")?;
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        handshake.write_u16::<BigEndian>(25565)?;
+        handshake.write_var(1)?;
+        test_util::write_packet(&mut client, 0x00, &handshake, false)?;
+
+        while connection.state == ConnectionState::Handshake {
+            connection.tick(&server)?;
+        }
+        assert_eq!(
+            Some("198.51.100.4:51234".parse()?),
+            connection.proxied_addr()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_cannot_switch_to_play() {
+        let (mut connection, _client) = test_util::connection();
+        let err = connection.set_state(ConnectionState::Play).unwrap_err();
+        assert_eq!("illegal state change: Handshake -> Play", err.to_string());
+        assert_eq!(ConnectionState::Handshake, connection.state);
+    }
+
+    #[test]
+    fn empty_body_round_trips_compressed() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+        connection.compressed = true;
+        connection.set_state(ConnectionState::Status)?;
+
+        test_util::write_packet(&mut client, StatusRequest::ID, &[], true)?;
+        let outcome = connection.tick(&server)?;
+        assert_eq!(1, outcome.packets_handled);
+        let (id, body) = test_util::read_packet(&mut client, true)?;
+        assert_eq!(StatusResponse::ID, id);
+        assert!(!body.is_empty());
+
+        connection.send_raw(0x00, &[])?;
+        assert_eq!((0x00, vec![]), test_util::read_packet(&mut client, true)?);
+        Ok(())
+    }
+
+    #[test]
+    fn short_decompressed_data_is_described() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+        connection.compressed = true;
+
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&[0x00; 10])?;
+        let mut frame = vec![];
+        frame.write_var(100)?;
+        frame.extend(encoder.finish()?);
+        client.write_var::<i32>(frame.len().try_into()?)?;
+        client.write_all(&frame)?;
+
+        let err = connection
+            .tick(&server)
+            .err()
+            .context("the frame was accepted")?;
+        let message = format!("{err:#}");
+        assert!(
+            message.contains("decompressed to 10 bytes, but 100 were declared"),
+            "{message}"
+        );
+        assert!(message.contains("starts with [78,"), "{message}");
+        Ok(())
+    }
+
+    #[test]
+    fn status_without_ping_times_out() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+        connection.set_status_timeout(Duration::from_millis(50));
+
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        handshake.write_u16::<BigEndian>(25565)?;
+        handshake.write_var(1)?;
+        let mut buf = vec![];
+        test_util::write_packet(&mut buf, 0x00, &handshake, false)?;
+        test_util::write_packet(&mut buf, StatusRequest::ID, &[], false)?;
+        client.write_all(&buf)?;
+
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::Close));
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_wraps_packets_in_delimiters() -> Result<()> {
+        let send_pings = |connection: &mut Connection| -> Result<()> {
+            connection.send_packet(PingResponse(1))?;
+            connection.send_packet(PingResponse(2))
+        };
+        let frame_ids = |client: &mut test_util::DuplexStream, count| -> Result<Vec<i32>> {
+            (0..count)
+                .map(|_| Ok(test_util::read_packet(client, false)?.0))
+                .collect()
+        };
+
+        let (mut connection, mut client) = test_util::connection();
+        connection.handshake = Some(handshake(BundleDelimiter::MIN_PROTOCOL_VERSION));
+        connection.bundle(send_pings)?;
+        let ping = PingResponse::ID;
+        assert_eq!(vec![0x00, ping, ping, 0x00], frame_ids(&mut client, 4)?);
+
+        let (mut connection, mut client) = test_util::connection();
+        connection.handshake = Some(handshake(761));
+        connection.bundle(send_pings)?;
+        drop(connection);
+        let mut rest = vec![];
+        assert_eq!(vec![ping, ping], frame_ids(&mut client, 2)?);
+        client.read_to_end(&mut rest)?;
+        assert!(rest.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn error_kick_links_to_issues() -> Result<()> {
+        let reason = serde_json::to_value(error_kick_reason("oops"))?;
+        let report = reason
+            .as_array()
+            .and_then(|parts| parts.last())
+            .context("the reason isn't sequential")?;
+        assert_eq!("Report this", report["text"]);
+        assert_eq!("open_url", report["clickEvent"]["action"]);
+        assert_eq!(crate::ISSUE_URL, report["clickEvent"]["value"]);
+        Ok(())
+    }
+
+    #[test]
+    fn long_errors_are_truncated() -> Result<()> {
+        let error = "\u{00e9}rror ".repeat(2048);
+        let reason = error_kick_reason(&error);
+        let json = reason.to_json_string(JsonStringType::Short)?;
+        assert!(json.len() < 4 * MAX_ERROR_KICK_CHARS);
+
+        let details = serde_json::to_value(&reason)?[2]["text"]
+            .as_str()
+            .context("the error details are missing")?
+            .to_string();
+        assert_eq!(MAX_ERROR_KICK_CHARS + 1, details.chars().count());
+        assert!(details.ends_with('\u{2026}'));
+        Ok(())
+    }
+
+    #[test]
+    fn compression_level_is_used() -> Result<()> {
+        let body = (0..16384_u32)
+            .map(|i| (i % 7 * i % 13) as u8)
+            .collect::<Vec<_>>();
+        let compressed_len = |level| -> Result<usize> {
+            let (mut connection, mut client) = test_util::connection();
+            connection.compressed = true;
+            connection.set_compression_level(level);
+            connection.send_raw(0x00, &body)?;
+            Ok(read_frame(&mut client)?.len())
+        };
+
+        let fast = compressed_len(1)?;
+        let best = compressed_len(9)?;
+        assert!(
+            best < fast,
+            "level 9 ({best} bytes) isn't smaller than level 1 ({fast} bytes)"
+        );
+        assert_eq!(best, compressed_len(100)?);
+        Ok(())
+    }
+
+    #[test]
+    fn send_raw_matches_send_packet() -> Result<()> {
+        let (mut typed, mut typed_client) = test_util::connection();
+        typed.send_packet(PingResponse(0x1234))?;
+        let (mut raw, mut raw_client) = test_util::connection();
+        raw.send_raw(PingResponse::ID, &0x1234_i64.to_be_bytes())?;
+
+        assert_eq!(read_frame(&mut typed_client)?, read_frame(&mut raw_client)?);
+        Ok(())
+    }
+
+    #[test]
+    fn slow_writes_keep_framing() -> Result<()> {
+        let stream = TrickleStream::default();
+        let written = Arc::clone(&stream.written);
+        let mut connection = Connection::new(stream);
+
+        let body = (0..100).collect::<Vec<u8>>();
+        connection.send_raw(0x01, &body)?;
+        connection.send_packet(PingResponse(0x1234))?;
+        assert!(connection.has_pending_writes());
+        while connection.has_pending_writes() {
+            connection.flush_outbound()?;
+        }
+
+        let (mut expected, mut expected_client) = test_util::connection();
+        expected.send_raw(0x01, &body)?;
+        expected.send_packet(PingResponse(0x1234))?;
+        let mut expected_bytes = read_frame(&mut expected_client)?;
+        expected_bytes.extend(read_frame(&mut expected_client)?);
+        assert_eq!(expected_bytes, *written.lock().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_response_is_written_before_closing() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let stream = TrickleStream {
+            incoming: io::Cursor::new(vec![0xfe, 0x01]),
+            ..Default::default()
+        };
+        let written = Arc::clone(&stream.written);
+        let mut connection = Connection::new(stream);
+
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::DoNothing));
+        assert!(written.lock().unwrap().is_empty());
+
+        let outcome = connection.tick(&server)?;
+        assert!(matches!(outcome.action, ConnectionAction::Close));
+        assert!(!connection.has_pending_writes());
+
+        let written = written.lock().unwrap();
+        let len = u16::from_be_bytes([written[1], written[2]]);
+        assert_eq!(0xff, written[0]);
+        assert_eq!(3 + 2 * usize::from(len), written.len());
+        Ok(())
+    }
+
+    #[test]
+    fn net_logs_use_the_net_target() -> Result<()> {
+        test_util::capture_logs();
+        let (mut connection, _client) = test_util::connection();
+        connection.send_server_links(&[])?;
+
+        let target = test_util::logged_target(
+            "Not sending server links to a client that doesn't support them.",
+        );
+        assert_eq!(Some(LOG_TARGET), target.as_deref());
+        Ok(())
+    }
+
+    // Accepts three bytes, then refuses the next write as a full non-blocking socket would
+    #[derive(Default)]
+    struct TrickleStream {
+        incoming: io::Cursor<Vec<u8>>,
+        written: Arc<Mutex<Vec<u8>>>,
+        blocked: bool,
+    }
+
+    impl Read for TrickleStream {
+        // Goes quiet instead of closing once everything has been read
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.incoming.read(buf)? {
+                0 if !buf.is_empty() => Err(ErrorKind::WouldBlock.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl ConnectionIo for TrickleStream {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for TrickleStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if !self.blocked {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+
+            let len = buf.len().min(3);
+            self.written.lock().unwrap().extend(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn handshake(version: i32) -> Handshake {
+        Handshake {
+            version,
+            server_addr: "localhost".into(),
+            server_port: 25565,
+            next_state: NextState::Login,
+        }
+    }
+
+    fn read_frame(client: &mut impl Read) -> Result<Vec<u8>> {
+        let len = client.read_var::<i32>()?;
+        let mut frame = vec![];
+        frame.write_var(len)?;
+        client.take(len.try_into()?).read_to_end(&mut frame)?;
+        Ok(frame)
+    }
 }
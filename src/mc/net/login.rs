@@ -9,31 +9,21 @@ use uuid::Uuid;
 
 packets_from_client!(decode, "login", [LoginStart]);
 
+#[derive(Clone, Debug)]
 pub struct LoginStart {
     pub name: String,
     pub uuid: Option<Uuid>,
 }
 
 impl PacketFromClient for LoginStart {
-    fn id() -> i32
-    where
-        Self: Sized,
-    {
-        0x00
-    }
+    const ID: i32 = 0x00;
 
     fn read<R: Read>(buf: &mut R) -> Result<Self> {
         let name = buf.read_string().context("failed to read the username")?;
 
         let uuid = buf
-            .read_bool()
-            .context("failed to read the boolean indicating the UUID")?;
-        let uuid = if uuid {
-            let uuid = buf.read_uuid().context("failed to read the UUID")?;
-            Some(uuid)
-        } else {
-            None
-        };
+            .read_optional(PacketReadExt::read_uuid)
+            .context("failed to read the UUID")?;
 
         let packet = Self { name, uuid };
         Ok(packet)
@@ -41,9 +31,18 @@ impl PacketFromClient for LoginStart {
 
     fn handle(
         self: Box<Self>,
-        _connection: &mut Connection,
-        _server: &Server,
+        connection: &mut Connection,
+        server: &Server,
     ) -> Result<ConnectionAction> {
+        if server.is_shutting_down() {
+            connection
+                .send_kick(Text::from(
+                    "The server is restarting, please try again soon!",
+                ))
+                .context("failed to turn away the player during shutdown")?;
+            return Ok(ConnectionAction::Close);
+        }
+
         let action = ConnectionAction::CreatePlayer {
             username: self.name,
         };
@@ -51,12 +50,11 @@ impl PacketFromClient for LoginStart {
     }
 }
 
+#[derive(Debug)]
 pub struct SetCompression(pub i32);
 
 impl PacketFromServer for SetCompression {
-    fn id() -> i32 {
-        0x03
-    }
+    const ID: i32 = 0x03;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_var(self.0)
@@ -64,6 +62,7 @@ impl PacketFromServer for SetCompression {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct LoginSuccess {
     pub uuid: Uuid,
     pub name: String,
@@ -71,9 +70,7 @@ pub struct LoginSuccess {
 }
 
 impl PacketFromServer for LoginSuccess {
-    fn id() -> i32 {
-        0x02
-    }
+    const ID: i32 = 0x02;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_uuid(&self.uuid)
@@ -81,23 +78,14 @@ impl PacketFromServer for LoginSuccess {
         buf.write_str(&self.name)
             .context("failed to write the username")?;
 
-        let property_len = self
-            .properties
-            .len()
-            .try_into()
-            .context("the property count doesn't fit in an i32")?;
-        buf.write_var::<i32>(property_len)
-            .context("failed to write the property count")?;
-        for property in &self.properties {
-            property
-                .write(buf)
-                .context("failed to write the property")?;
-        }
-
-        Ok(())
+        buf.write_prefixed_array(&self.properties, |buf, property| {
+            property.write(buf).context("failed to write the property")
+        })
+        .context("failed to write the properties")
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct LoginProperty {
     pub name: String,
     pub value: String,
@@ -110,26 +98,18 @@ impl LoginProperty {
             .context("failed to write the name")?;
         buf.write_str(&self.value)
             .context("failed to write the value")?;
-        if let Some(signature) = &self.signature {
-            buf.write_bool(true)
-                .context("failed to write the boolean indicating the signature")?;
-            buf.write_str(signature)
-                .context("failed to write the signature")
-        } else {
-            buf.write_bool(false)
-                .context("failed to write the boolean indicating the signature")
-        }
+        buf.write_optional(&self.signature, |buf, signature| buf.write_str(signature))
+            .context("failed to write the signature")
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct LoginDisconnect {
     pub reason: Text,
 }
 
 impl PacketFromServer for LoginDisconnect {
-    fn id() -> i32 {
-        0x00
-    }
+    const ID: i32 = 0x00;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_json(&self.reason)
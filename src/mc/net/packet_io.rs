@@ -1,13 +1,20 @@
+use crate::mc::nbt::NbtValue;
 use crate::mc::world::BlockPos;
 use crate::mc::Identifier;
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num_enum::TryFromPrimitive;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::any;
+use std::io::Read;
 use std::num::TryFromIntError;
 use std::ops::{BitAnd, BitOrAssign, Shl};
 use uuid::Uuid;
 
+// Vanilla's limit on characters; a character takes at most 3 bytes in Java's modified UTF-8
+pub const MAX_STRING_LEN: usize = 32767;
+
 pub trait PacketReadExt: ReadBytesExt {
     fn read_bool(&mut self) -> Result<bool> {
         let byte = self.read_u8().context("failed to read the boolean byte")?;
@@ -25,12 +32,35 @@ pub trait PacketReadExt: ReadBytesExt {
         }
     }
 
+    fn read_len(&mut self) -> Result<usize> {
+        let len = self.read_var::<i32>()?;
+        if len < 0 {
+            bail!("the length is negative ({len})");
+        }
+        len.try_into().context("the length doesn't fit in a usize")
+    }
+
+    fn read_enum<E: TryFromPrimitive<Primitive = i32>>(&mut self) -> Result<E> {
+        let discriminant = self.read_var::<i32>()?;
+        E::try_from_primitive(discriminant).map_err(|_| {
+            let name = any::type_name::<E>()
+                .rsplit("::")
+                .next()
+                .unwrap_or_default();
+            anyhow!("invalid {name} discriminant {discriminant}")
+        })
+    }
+
     fn read_string(&mut self) -> Result<String> {
         let len = self
-            .read_var::<i32>()
-            .context("failed to read the string length")?
-            .try_into()
-            .context("the string length doesn't fit in a usize")?;
+            .read_len()
+            .context("failed to read the string length")?;
+        if len > MAX_STRING_LEN * 3 {
+            bail!(
+                "the string is {len} bytes long, but the limit is {}",
+                MAX_STRING_LEN * 3
+            );
+        }
 
         let mut bytes = vec![0; len];
         for byte in bytes.iter_mut() {
@@ -39,6 +69,34 @@ pub trait PacketReadExt: ReadBytesExt {
         String::from_utf8(bytes).context("the string is not valid UTF-8")
     }
 
+    fn read_byte_array(&mut self) -> Result<Vec<u8>> {
+        let len = self
+            .read_len()
+            .context("failed to read the byte array length")?
+            .try_into()
+            .context("the byte array length doesn't fit in a u64")?;
+
+        // Grow the buffer as bytes actually arrive rather than trusting the declared length
+        let mut bytes = vec![];
+        Read::take(&mut *self, len)
+            .read_to_end(&mut bytes)
+            .context("failed to read the byte array")?;
+        if bytes.len() as u64 != len {
+            bail!(
+                "expected {len} bytes but the byte array ended after {}",
+                bytes.len()
+            );
+        }
+        Ok(bytes)
+    }
+
+    fn read_remaining(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.read_to_end(&mut bytes)
+            .context("failed to read the remaining bytes")?;
+        Ok(bytes)
+    }
+
     fn read_json<D: DeserializeOwned>(&mut self) -> Result<D> {
         let string = self
             .read_string()
@@ -60,16 +118,58 @@ pub trait PacketReadExt: ReadBytesExt {
         fastnbt::from_reader(self).context("failed to read and deserialize the NBT value")
     }
 
+    fn read_nbt_value(&mut self) -> Result<Option<NbtValue>> {
+        NbtValue::read(self).context("failed to read the NBT value")
+    }
+
     fn read_block_pos(&mut self) -> Result<BlockPos> {
+        // Shifting a signed value sign-extends each coordinate
         let encoded = self
-            .read_u64::<BigEndian>()
-            .context("failed to read the block position as a u64")?;
+            .read_i64::<BigEndian>()
+            .context("failed to read the block position as an i64")?;
         let x = (encoded >> 38) as i32;
         let y = (encoded << 52 >> 52) as i32;
         let z = (encoded << 26 >> 38) as i32;
         Ok(BlockPos { x, y, z })
     }
 
+    fn read_angle(&mut self) -> Result<f32> {
+        let steps = self.read_u8().context("failed to read the angle byte")?;
+        Ok(f32::from(steps) * 360.0 / 256.0)
+    }
+
+    fn read_fixed_point_i32(&mut self, scale: f64) -> Result<f64> {
+        let fixed = self
+            .read_i32::<BigEndian>()
+            .context("failed to read the fixed-point value as an i32")?;
+        Ok(f64::from(fixed) / scale)
+    }
+
+    fn read_optional<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        let present = self
+            .read_bool()
+            .context("failed to read the boolean indicating the value")?;
+        if present {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_prefixed_array<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let len = self.read_len().context("failed to read the array length")?;
+        // Like byte arrays, this grows as elements arrive instead of trusting the length
+        let mut elements = vec![];
+        for index in 0..len {
+            let element = read(self).with_context(|| format!("failed to read element {index}"))?;
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+
     fn read_uuid(&mut self) -> Result<Uuid> {
         let high = self
             .read_u64::<BigEndian>()
@@ -83,6 +183,48 @@ pub trait PacketReadExt: ReadBytesExt {
 
 impl<R: ReadBytesExt> PacketReadExt for R {}
 
+// Fields of packets declared with play_packets!. i32s are VarInts since that's how the protocol
+// sends nearly all of them.
+pub trait PacketField: Sized {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self>;
+}
+
+impl PacketField for bool {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self> {
+        buf.read_bool()
+    }
+}
+
+impl PacketField for i8 {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self> {
+        Ok(buf.read_i8()?)
+    }
+}
+
+impl PacketField for u8 {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self> {
+        Ok(buf.read_u8()?)
+    }
+}
+
+impl PacketField for i32 {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self> {
+        buf.read_var()
+    }
+}
+
+impl PacketField for i64 {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self> {
+        Ok(buf.read_i64::<BigEndian>()?)
+    }
+}
+
+impl PacketField for String {
+    fn read_field<R: Read>(buf: &mut R) -> Result<Self> {
+        buf.read_string()
+    }
+}
+
 pub trait PacketWriteExt: WriteBytesExt {
     fn write_bool(&mut self, bool: bool) -> Result<()> {
         self.write_u8(bool.into())
@@ -120,6 +262,17 @@ pub trait PacketWriteExt: WriteBytesExt {
         Ok(())
     }
 
+    fn write_byte_array(&mut self, bytes: &[u8]) -> Result<()> {
+        let len = bytes
+            .len()
+            .try_into()
+            .context("the byte array length doesn't fit in an i32")?;
+        self.write_var::<i32>(len)
+            .context("failed to write the byte array length")?;
+        self.write_all(bytes)
+            .context("failed to write the byte array")
+    }
+
     fn write_json(&mut self, value: &impl Serialize) -> Result<()> {
         let string = serde_json::to_string(value).context("failed to serialize as JSON")?;
         self.write_str(&string)
@@ -135,6 +288,17 @@ pub trait PacketWriteExt: WriteBytesExt {
         fastnbt::to_writer(self, value).context("failed to serialize and write the NBT value")
     }
 
+    fn write_network_nbt(&mut self, value: &impl Serialize) -> Result<()> {
+        let named = fastnbt::to_bytes(value).context("failed to serialize the NBT value")?;
+        let Some((&tag, [0, 0, payload @ ..])) = named.split_first() else {
+            bail!("the serialized NBT value doesn't have an empty root name");
+        };
+        self.write_u8(tag)
+            .context("failed to write the root tag type")?;
+        self.write_all(payload)
+            .context("failed to write the NBT payload")
+    }
+
     fn write_block_pos(&mut self, pos: &BlockPos) -> Result<()> {
         let x = ((pos.x & 0x3ffffff) as u64) << 38;
         let z = ((pos.z & 0x3ffffff) as u64) << 12;
@@ -143,6 +307,68 @@ pub trait PacketWriteExt: WriteBytesExt {
             .context("failed to write the block position as a u64")
     }
 
+    // Angles are sent in steps of 1/256 of a full turn
+    fn write_angle(&mut self, degrees: f32) -> Result<()> {
+        let steps = (degrees.rem_euclid(360.0) * 256.0 / 360.0).round() as u32;
+        self.write_u8((steps % 256) as u8)
+            .context("failed to write the angle byte")
+    }
+
+    fn write_finite_f32(&mut self, value: f32) -> Result<()> {
+        if !value.is_finite() {
+            bail!("the value must be finite, but it's {value}");
+        }
+        self.write_f32::<BigEndian>(value)
+            .context("failed to write the f32")
+    }
+
+    fn write_finite_f64(&mut self, value: f64) -> Result<()> {
+        if !value.is_finite() {
+            bail!("the value must be finite, but it's {value}");
+        }
+        self.write_f64::<BigEndian>(value)
+            .context("failed to write the f64")
+    }
+
+    fn write_fixed_point_i32(&mut self, value: f64, scale: f64) -> Result<()> {
+        let fixed = (value * scale).round();
+        if !fixed.is_finite() || fixed < f64::from(i32::MIN) || fixed > f64::from(i32::MAX) {
+            bail!("{value} doesn't fit in an i32 when scaled by {scale}");
+        }
+        self.write_i32::<BigEndian>(fixed as i32)
+            .context("failed to write the fixed-point value as an i32")
+    }
+
+    fn write_optional<T>(
+        &mut self,
+        option: &Option<T>,
+        write: impl FnOnce(&mut Self, &T) -> Result<()>,
+    ) -> Result<()> {
+        self.write_bool(option.is_some())
+            .context("failed to write the boolean indicating the value")?;
+        if let Some(value) = option {
+            write(self, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_prefixed_array<T>(
+        &mut self,
+        array: &[T],
+        mut write: impl FnMut(&mut Self, &T) -> Result<()>,
+    ) -> Result<()> {
+        let len = array
+            .len()
+            .try_into()
+            .context("the array length doesn't fit in an i32")?;
+        self.write_var::<i32>(len)
+            .context("failed to write the array length")?;
+        for element in array {
+            write(self, element)?;
+        }
+        Ok(())
+    }
+
     fn write_uuid(&mut self, uuid: &Uuid) -> Result<()> {
         let (high, low) = uuid.as_u64_pair();
         self.write_u64::<BigEndian>(high)
@@ -154,6 +380,35 @@ pub trait PacketWriteExt: WriteBytesExt {
 
 impl<W: WriteBytesExt> PacketWriteExt for W {}
 
+pub fn var_len<V: VarInt>(mut var: V) -> usize {
+    let zero = V::from(0);
+    let mut len = 1;
+    loop {
+        var = var.unsigned_shr(7);
+        if var == zero {
+            return len;
+        }
+        len += 1;
+    }
+}
+
+// Panics if the buffer is shorter than var_len(var), which MAX_VAR_LEN always covers
+pub fn write_var_into<V: VarInt>(buf: &mut [u8], mut var: V) -> usize {
+    let zero = V::from(0);
+    let segment_bits = V::from(0x7f);
+    let mut len = 0;
+    loop {
+        let next: u8 = (var & segment_bits).try_into().unwrap();
+        var = var.unsigned_shr(7);
+        if var == zero {
+            buf[len] = next;
+            return len + 1;
+        }
+        buf[len] = next | 0x80;
+        len += 1;
+    }
+}
+
 pub trait VarInt:
     Copy
     + PartialEq
@@ -229,6 +484,7 @@ impl<V: VarInt> Default for PartialVarInt<V> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mc::net::test_util;
     use std::fmt::Debug;
 
     #[test]
@@ -335,6 +591,107 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn var_into_slice_matches_write_var() -> Result<()> {
+        fn check<V: VarInt>(var: V) -> Result<()> {
+            let mut expected = vec![];
+            expected.write_var(var)?;
+
+            let mut buf = [0; 10];
+            let len = write_var_into(&mut buf, var);
+            assert_eq!(expected, buf[..len]);
+            assert_eq!(expected.len(), var_len(var));
+            Ok(())
+        }
+
+        for var in [
+            0,
+            1,
+            127,
+            128,
+            16383,
+            16384,
+            2097151,
+            2097152,
+            i32::MAX,
+            -1,
+            i32::MIN,
+        ] {
+            check(var)?;
+        }
+        for var in [0, 127, 128, i64::from(i32::MAX) + 1, i64::MAX, -1, i64::MIN] {
+            check(var)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn write_finite_float() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_finite_f32(1.5)?;
+        buf.write_finite_f64(-2.0)?;
+        let mut expected = 1.5_f32.to_be_bytes().to_vec();
+        expected.extend_from_slice(&(-2.0_f64).to_be_bytes());
+        assert_eq!(expected, buf);
+        Ok(())
+    }
+
+    #[test]
+    fn write_non_finite_float() {
+        let mut buf = vec![];
+        assert!(buf.write_finite_f32(f32::NAN).is_err());
+        assert!(buf.write_finite_f64(f64::NAN).is_err());
+        assert!(buf.write_finite_f64(f64::INFINITY).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn fixed_point_positive_32() -> Result<()> {
+        fixed_point(12.5, 32.0, 400)
+    }
+
+    #[test]
+    fn fixed_point_negative_32() -> Result<()> {
+        fixed_point(-12.5, 32.0, -400)
+    }
+
+    #[test]
+    fn fixed_point_positive_4096() -> Result<()> {
+        fixed_point(0.25, 4096.0, 1024)
+    }
+
+    #[test]
+    fn fixed_point_negative_4096() -> Result<()> {
+        fixed_point(-3.75, 4096.0, -15360)
+    }
+
+    #[test]
+    fn fixed_point_rounds() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_fixed_point_i32(0.99, 8.0)?;
+        buf.write_fixed_point_i32(-0.99, 8.0)?;
+        assert_eq!([8_i32.to_be_bytes(), (-8_i32).to_be_bytes()].concat(), buf);
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_point_out_of_range() {
+        let mut buf = vec![];
+        assert!(buf.write_fixed_point_i32(f64::NAN, 8.0).is_err());
+        assert!(buf.write_fixed_point_i32(f64::INFINITY, 8.0).is_err());
+        assert!(buf.write_fixed_point_i32(f64::from(i32::MAX), 8.0).is_err());
+        assert!(buf.write_fixed_point_i32(f64::from(i32::MIN), 8.0).is_err());
+        assert!(buf.is_empty());
+    }
+
+    fn fixed_point(value: f64, scale: f64, expected: i32) -> Result<()> {
+        let mut buf = vec![];
+        buf.write_fixed_point_i32(value, scale)?;
+        assert_eq!(expected.to_be_bytes(), &buf[..]);
+        assert_eq!(value, (&buf[..]).read_fixed_point_i32(scale)?);
+        Ok(())
+    }
+
     const TEST_STRING: &str = "Hello Minestodon";
 
     #[test]
@@ -345,6 +702,32 @@ mod tests {
         Ok(())
     }
 
+    #[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+    #[repr(i32)]
+    enum Hand {
+        Main = 0,
+        Off = 1,
+    }
+
+    #[test]
+    fn read_enum_discriminants() -> Result<()> {
+        assert_eq!(Hand::Off, (&[1][..]).read_enum::<Hand>()?);
+        let err = (&[2][..]).read_enum::<Hand>().unwrap_err();
+        assert_eq!("invalid Hand discriminant 2", err.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn negative_lengths_are_rejected() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_var(-1)?;
+        let err = (&buf[..]).read_string().unwrap_err();
+        assert_eq!("the length is negative (-1)", err.root_cause().to_string());
+        let err = (&buf[..]).read_byte_array().unwrap_err();
+        assert_eq!("the length is negative (-1)", err.root_cause().to_string());
+        Ok(())
+    }
+
     #[test]
     fn write_str() -> Result<()> {
         let mut buf = vec![];
@@ -353,6 +736,163 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn byte_array_round_trip() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_byte_array(&[1, 2, 3])?;
+        buf.extend_from_slice(&[4, 5]);
+
+        let mut slice = &buf[..];
+        assert_eq!(vec![1, 2, 3], slice.read_byte_array()?);
+        assert_eq!(vec![4, 5], slice.read_remaining()?);
+        assert!(slice.read_remaining()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_byte_array() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_var(i32::MAX)?;
+        buf.extend_from_slice(&[1, 2, 3]);
+        assert!((&buf[..]).read_byte_array().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn option_round_trip() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_optional(&Some(-5), |buf, value| buf.write_var::<i32>(*value))?;
+        buf.write_optional(&None::<i32>, |buf, value| buf.write_var(*value))?;
+        assert_eq!(vec![1, 0xfb, 0xff, 0xff, 0xff, 0x0f, 0], buf);
+
+        let mut slice = &buf[..];
+        assert_eq!(Some(-5), slice.read_optional(|buf| buf.read_var::<i32>())?);
+        assert_eq!(None, slice.read_optional(|buf| buf.read_var::<i32>())?);
+        assert!(slice.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn string_through_stream() -> Result<()> {
+        let (mut server, mut client) = test_util::duplex();
+        client.write_str(TEST_STRING)?;
+        client.write_var(i32::MIN)?;
+        assert_eq!(TEST_STRING, server.read_string()?);
+        assert_eq!(i32::MIN, server.read_var::<i32>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn block_pos_round_trip() -> Result<()> {
+        for pos in [
+            BlockPos::new(0, 0, 0),
+            BlockPos::new(33554431, 2047, 33554431),
+            BlockPos::new(-30000000, -64, 30000000),
+        ] {
+            let mut buf = vec![];
+            buf.write_block_pos(&pos)?;
+            assert_eq!(pos, (&buf[..]).read_block_pos()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn angle_round_trip() -> Result<()> {
+        for degrees in [0.0, 45.0, 90.5, 180.0, 359.0, -90.0, 720.0 + 30.0] {
+            let mut buf = vec![];
+            buf.write_angle(degrees)?;
+            let read = (&buf[..]).read_angle()?;
+            let difference = (read - f32::rem_euclid(degrees, 360.0)).abs();
+            assert!(difference.min(360.0 - difference) <= 360.0 / 512.0);
+        }
+
+        for degrees in [0.0, 360.0, 359.9] {
+            let mut buf = vec![];
+            buf.write_angle(degrees)?;
+            assert_eq!(vec![0], buf);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prefixed_arrays_and_options() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_prefixed_array::<i32>(&[], |buf, value| buf.write_var(*value))?;
+        assert_eq!(vec![0], buf);
+        assert!((&buf[..])
+            .read_prefixed_array(|buf| buf.read_var::<i32>())?
+            .is_empty());
+
+        let values = vec![Some(1), None, Some(300)];
+        let mut buf = vec![];
+        buf.write_prefixed_array(&values, |buf, value| {
+            buf.write_optional(value, |buf, value| buf.write_var::<i32>(*value))
+        })?;
+        assert_eq!(vec![3, 1, 1, 0, 1, 0xac, 0x02], buf);
+        let mut slice = &buf[..];
+        let read = slice.read_prefixed_array(|buf| buf.read_optional(|buf| buf.read_var()))?;
+        assert_eq!(values, read);
+        assert!(slice.is_empty());
+
+        assert!((&[2, 1][..])
+            .read_prefixed_array(|buf| buf.read_var::<i32>())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn huge_strings_are_rejected() -> Result<()> {
+        let mut buf = vec![];
+        buf.write_var(i32::MAX)?;
+        let err = (&buf[..]).read_string().unwrap_err();
+        assert!(err.to_string().contains("the limit is 98301"));
+        Ok(())
+    }
+
+    #[test]
+    fn write_nested_nbt() -> Result<()> {
+        #[derive(Serialize)]
+        struct Outer {
+            byte: i8,
+            name: &'static str,
+            scale: f32,
+            height: f64,
+            inner: Inner,
+        }
+
+        #[derive(Serialize)]
+        struct Inner {
+            value: i32,
+            longs: Vec<i64>,
+        }
+
+        let value = Outer {
+            byte: 1,
+            name: "a",
+            scale: 1.0,
+            height: 2.0,
+            inner: Inner {
+                value: 3,
+                longs: vec![4],
+            },
+        };
+        let mut buf = vec![];
+        buf.write_nbt(&value)?;
+
+        let mut expected = vec![0x0a, 0, 0];
+        expected.extend(b"\x01\x00\x04byte\x01");
+        expected.extend(b"\x08\x00\x04name\x00\x01a");
+        expected.extend(b"\x05\x00\x05scale\x3f\x80\x00\x00");
+        expected.extend(b"\x06\x00\x06height\x40\x00\x00\x00\x00\x00\x00\x00");
+        expected.extend(b"\x0a\x00\x05inner");
+        expected.extend(b"\x03\x00\x05value\x00\x00\x00\x03");
+        expected.extend(b"\x09\x00\x05longs\x04\x00\x00\x00\x01");
+        expected.extend(4_i64.to_be_bytes());
+        expected.extend([0, 0]);
+        assert_eq!(expected, buf);
+        Ok(())
+    }
+
     fn test_string_bytes() -> Result<Vec<u8>> {
         let mut buf = vec![];
         buf.write_var::<i32>(TEST_STRING.len().try_into()?)?;
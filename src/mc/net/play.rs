@@ -1,14 +1,89 @@
-use crate::mc::net::packet_io::PacketWriteExt;
-use crate::mc::net::PacketFromServer;
+use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+use crate::mc::net::play::chat::PlayerSession;
+use crate::mc::net::play::container::CloseContainerFromClient;
+use crate::mc::net::play::resource_pack::ResourcePackResponse;
+use crate::mc::net::play::sign::UpdateSign;
+use crate::mc::net::{Connection, DecodedPacket, PacketFromClient, PacketFromServer, LOG_TARGET};
+use crate::mc::text::Text;
 use crate::mc::Identifier;
+use crate::server::{ConnectionAction, Server};
+use crate::{packets_from_client, play_packets};
 use anyhow::{Context, Result};
+use byteorder::{BigEndian, WriteBytesExt};
+use log::debug;
 use minestodon_macros::minecraft;
 use std::borrow::Cow;
-use std::io::Write;
+use std::io::{Read, Write};
 
+pub mod chat;
+pub mod container;
+pub mod entity;
+pub mod link;
 pub mod player;
+pub mod recipe;
+pub mod resource_pack;
+pub mod server_data;
 pub mod setup;
+pub mod sign;
+pub mod sound;
+pub mod tag;
+pub mod world;
 
+packets_from_client!(
+    decode_packets,
+    [
+        CloseContainerFromClient,
+        PluginMessageFromClient,
+        PlayerSession,
+        ResourcePackResponse,
+        UpdateSign,
+    ],
+    skip_unknown,
+);
+
+play_packets!(
+    decode_simple_packets,
+    [
+        ConfirmTeleport(0x00) { teleport_id: i32 } => |_, _, _| Ok(ConnectionAction::DoNothing),
+        ClientInformation(0x07) {
+            locale: String,
+            view_distance: i8,
+            chat_mode: i32,
+            chat_colors: bool,
+            displayed_skin_parts: u8,
+            main_hand: i32,
+            text_filtering: bool,
+            allow_server_listings: bool,
+        } => handle_client_information,
+        KeepAliveFromClient(0x11) { id: i64 } => |packet, connection, _| {
+            connection.answer_keep_alive(packet.id);
+            Ok(ConnectionAction::DoNothing)
+        },
+    ],
+);
+
+pub fn decode(id: i32, buf: &mut impl Read) -> Result<Option<Box<dyn DecodedPacket>>> {
+    match decode_packets(id, buf)? {
+        Some(packet) => Ok(Some(packet)),
+        None => decode_simple_packets(id, buf),
+    }
+}
+
+fn handle_client_information(
+    packet: ClientInformation,
+    _connection: &mut Connection,
+    _server: &Server,
+) -> Result<ConnectionAction> {
+    debug!(
+        target: LOG_TARGET,
+        "The client uses locale {} with a view distance of {}.",
+        packet.locale,
+        packet.view_distance
+    );
+    Ok(ConnectionAction::DoNothing)
+}
+
+#[derive(Clone, Debug)]
 pub struct PluginMessageFromServer {
     pub channel: Identifier,
     pub data: Vec<u8>,
@@ -33,9 +108,7 @@ impl PluginMessageFromServer {
 }
 
 impl PacketFromServer for PluginMessageFromServer {
-    fn id() -> i32 {
-        0x15
-    }
+    const ID: i32 = 0x15;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_identifier(&self.channel)
@@ -44,3 +117,112 @@ impl PacketFromServer for PluginMessageFromServer {
             .context("failed to write the data")
     }
 }
+
+#[derive(Clone, Debug)]
+pub struct PluginMessageFromClient {
+    pub channel: Identifier,
+    pub data: Vec<u8>,
+}
+
+impl PacketFromClient for PluginMessageFromClient {
+    const ID: i32 = 0x0c;
+
+    fn read<R: Read>(buf: &mut R) -> Result<Self> {
+        let channel = buf
+            .read_identifier()
+            .context("failed to read the channel")?;
+        let data = buf.read_remaining().context("failed to read the data")?;
+        Ok(Self { channel, data })
+    }
+
+    fn handle(
+        self: Box<Self>,
+        _connection: &mut Connection,
+        _server: &Server,
+    ) -> Result<ConnectionAction> {
+        debug!(
+            target: LOG_TARGET,
+            "Ignoring a {}-byte plugin message on channel {}.",
+            self.data.len(),
+            self.channel
+        );
+        Ok(ConnectionAction::DoNothing)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct KeepAliveFromServer {
+    pub id: i64,
+}
+
+impl PacketFromServer for KeepAliveFromServer {
+    const ID: i32 = 0x1f;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_i64::<BigEndian>(self.id)
+            .context("failed to write the keep-alive ID")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayDisconnect {
+    pub reason: Text,
+}
+
+impl PacketFromServer for PlayDisconnect {
+    const ID: i32 = 0x17;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_json(&self.reason)
+            .context("failed to write the reason")
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BundleDelimiter;
+
+impl BundleDelimiter {
+    // Bundles were added in 1.19.4
+    pub const MIN_PROTOCOL_VERSION: i32 = 762;
+}
+
+impl PacketFromServer for BundleDelimiter {
+    const ID: i32 = 0x00;
+
+    fn write<W: Write>(&self, _buf: &mut W) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::test_util;
+
+    #[test]
+    fn simple_packets_are_routed() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::play_connection();
+
+        let mut teleport = vec![];
+        teleport.write_var(7)?;
+        let mut information = vec![];
+        information.write_str("en_us")?;
+        information.extend([12, 0, 1, 0x7f, 1, 0, 1]);
+        let mut keep_alive = vec![];
+        keep_alive.write_i64::<BigEndian>(1234)?;
+
+        for (id, body) in [
+            (ConfirmTeleport::ID, teleport),
+            (ClientInformation::ID, information),
+            (KeepAliveFromClient::ID, keep_alive),
+        ] {
+            let mut body = &body[..];
+            let packet = decode(id, &mut body)?.context("the packet wasn't routed")?;
+            assert!(body.is_empty());
+            packet.handle(&mut connection, &server)?;
+        }
+        assert!(decode(0x7f, &mut &[][..])?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+use crate::mc::net::{Connection, PacketFromClient, PacketFromServer};
+use crate::mc::text::Text;
+use crate::server::{ConnectionAction, Server};
+use anyhow::{Context, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+// Signatures aren't verified yet, the session is only kept so chat can be decoded later
+#[derive(Clone, Debug)]
+pub struct ChatSession {
+    pub id: Uuid,
+    pub expires_at: i64,
+    pub public_key: Vec<u8>,
+    pub key_signature: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct PlayerSession(pub ChatSession);
+
+impl PacketFromClient for PlayerSession {
+    const ID: i32 = 0x20;
+
+    fn read<R: Read>(buf: &mut R) -> Result<Self> {
+        let id = buf.read_uuid().context("failed to read the session ID")?;
+        let expires_at = buf
+            .read_i64::<BigEndian>()
+            .context("failed to read the key expiry time")?;
+        let public_key = buf
+            .read_byte_array()
+            .context("failed to read the public key")?;
+        let key_signature = buf
+            .read_byte_array()
+            .context("failed to read the key signature")?;
+
+        let session = ChatSession {
+            id,
+            expires_at,
+            public_key,
+            key_signature,
+        };
+        Ok(Self(session))
+    }
+
+    fn handle(
+        self: Box<Self>,
+        connection: &mut Connection,
+        _server: &Server,
+    ) -> Result<ConnectionAction> {
+        connection.chat_session = Some(self.0);
+        Ok(ConnectionAction::DoNothing)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetActionBarText {
+    pub text: Text,
+}
+
+impl PacketFromServer for SetActionBarText {
+    const ID: i32 = 0x42;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        self.text.check_json_len()?;
+        buf.write_json(&self.text)
+            .context("failed to write the text")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SystemChatMessage {
+    pub text: Text,
+    // True shows the message above the hotbar instead of in the chat
+    pub overlay: bool,
+}
+
+impl PacketFromServer for SystemChatMessage {
+    const ID: i32 = 0x60;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        self.text.check_json_len()?;
+        buf.write_json(&self.text)
+            .context("failed to write the text")?;
+        buf.write_bool(self.overlay)
+            .context("failed to write whether the message is an overlay")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::{play, test_util};
+    use byteorder::WriteBytesExt;
+
+    #[test]
+    fn player_session_is_stored() -> Result<()> {
+        // Shaped like a vanilla session: a 294-byte DER RSA key and a 512-byte signature
+        let id = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        let public_key = [[0x30, 0x82, 0x01, 0x22].as_slice(), &[0xaa; 290]].concat();
+        let key_signature = vec![0x55; 512];
+        let mut body = vec![];
+        body.write_uuid(&id)?;
+        body.write_i64::<BigEndian>(1_700_000_000_000)?;
+        body.write_byte_array(&public_key)?;
+        body.write_byte_array(&key_signature)?;
+
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::connection();
+        let mut slice = &body[..];
+        let packet = play::decode(PlayerSession::ID, &mut slice)?.unwrap();
+        packet.handle(&mut connection, &server)?;
+        assert!(slice.is_empty());
+
+        let session = connection
+            .chat_session()
+            .context("the session wasn't stored")?;
+        assert_eq!(id, session.id);
+        assert_eq!(1_700_000_000_000, session.expires_at);
+        assert_eq!(public_key, session.public_key);
+        assert_eq!(key_signature, session.key_signature);
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_chat_is_refused() {
+        let text = Text::from("a".repeat(Text::MAX_JSON_LEN));
+        let mut buf = vec![];
+        let system = SystemChatMessage {
+            text: text.clone(),
+            overlay: false,
+        };
+        assert!(system.write(&mut buf).is_err());
+        assert!(SetActionBarText { text }.write(&mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::mc::net::{Connection, PacketFromClient, PacketFromServer};
+use crate::server::{ConnectionAction, Server};
+use anyhow::Context;
+use anyhow::Result;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+#[derive(Copy, Clone, Debug)]
+pub struct CloseContainerFromServer {
+    pub window_id: u8,
+}
+
+impl PacketFromServer for CloseContainerFromServer {
+    const ID: i32 = 0x0f;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_u8(self.window_id)
+            .context("failed to write the window ID")
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CloseContainerFromClient {
+    pub window_id: u8,
+}
+
+impl PacketFromClient for CloseContainerFromClient {
+    const ID: i32 = 0x0b;
+
+    fn read<R: Read>(buf: &mut R) -> Result<Self> {
+        let window_id = buf.read_u8().context("failed to read the window ID")?;
+        Ok(Self { window_id })
+    }
+
+    fn handle(
+        self: Box<Self>,
+        connection: &mut Connection,
+        server: &Server,
+    ) -> Result<ConnectionAction> {
+        if let Some(on_close) = server.config().on_container_closed {
+            on_close(connection, self.window_id);
+        }
+        Ok(ConnectionAction::DoNothing)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SetContainerProperty {
+    pub window_id: u8,
+    pub property: i16,
+    pub value: i16,
+}
+
+impl PacketFromServer for SetContainerProperty {
+    const ID: i32 = 0x11;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_u8(self.window_id)
+            .context("failed to write the window ID")?;
+        buf.write_i16::<BigEndian>(self.property)
+            .context("failed to write the property")?;
+        buf.write_i16::<BigEndian>(self.value)
+            .context("failed to write the value")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::{play, test_util};
+    use crate::server::ServerConfig;
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    #[test]
+    fn closing_a_container_fires_the_callback() -> Result<()> {
+        static CLOSED: AtomicU8 = AtomicU8::new(0);
+        let config = ServerConfig {
+            on_container_closed: Some(|_, window_id| CLOSED.store(window_id, Ordering::SeqCst)),
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let (mut connection, _client) = test_util::connection();
+
+        let packet = play::decode(CloseContainerFromClient::ID, &mut &[7][..])?.unwrap();
+        packet.handle(&mut connection, &server)?;
+        assert_eq!(7, CLOSED.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn write_close_container() -> Result<()> {
+        let mut buf = vec![];
+        CloseContainerFromServer { window_id: 7 }.write(&mut buf)?;
+        assert_eq!(vec![7], buf);
+        Ok(())
+    }
+
+    #[test]
+    fn write_container_property() -> Result<()> {
+        let mut buf = vec![];
+        let packet = SetContainerProperty {
+            window_id: 3,
+            property: 2,
+            value: -300,
+        };
+        packet.write(&mut buf)?;
+        assert_eq!(vec![3, 0x00, 0x02, 0xfe, 0xd4], buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use crate::mc::text::Text;
+use crate::mc::Identifier;
+use anyhow::{Context, Result};
+use byteorder::{BigEndian, WriteBytesExt};
+use minestodon_macros::minecraft;
+use std::io::Write;
+use uuid::Uuid;
+
+pub const ARMOR_STAND: i32 = 2;
+
+pub const MOVEMENT_SPEED: Identifier = minecraft!("generic.movement_speed");
+
+#[derive(Clone, Debug)]
+pub struct SpawnEntity {
+    pub entity_id: i32,
+    pub uuid: Uuid,
+    pub kind: i32,
+    pub pos: (f64, f64, f64),
+    pub pitch: u8,
+    pub yaw: u8,
+    pub head_yaw: u8,
+    pub data: i32,
+    pub velocity: [i16; 3],
+}
+
+impl PacketFromServer for SpawnEntity {
+    const ID: i32 = 0x00;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.entity_id)
+            .context("failed to write the entity ID")?;
+        buf.write_uuid(&self.uuid)
+            .context("failed to write the UUID")?;
+        buf.write_var(self.kind)
+            .context("failed to write the entity type")?;
+        let (x, y, z) = self.pos;
+        for (pos, axis) in [x, y, z].into_iter().zip(["X", "Y", "Z"]) {
+            buf.write_finite_f64(pos)
+                .with_context(|| format!("failed to write the {axis} position"))?;
+        }
+        buf.write_u8(self.pitch)
+            .context("failed to write the pitch")?;
+        buf.write_u8(self.yaw).context("failed to write the yaw")?;
+        buf.write_u8(self.head_yaw)
+            .context("failed to write the head yaw")?;
+        buf.write_var(self.data)
+            .context("failed to write the entity data")?;
+        for (velocity, axis) in self.velocity.iter().zip(["X", "Y", "Z"]) {
+            buf.write_i16::<BigEndian>(*velocity)
+                .with_context(|| format!("failed to write the {axis} velocity"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetEntityMetadata {
+    pub entity_id: i32,
+    pub entries: Vec<(u8, MetadataValue)>,
+}
+
+impl PacketFromServer for SetEntityMetadata {
+    const ID: i32 = 0x4e;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.entity_id)
+            .context("failed to write the entity ID")?;
+        for (index, value) in &self.entries {
+            buf.write_u8(*index)
+                .context("failed to write the metadata index")?;
+            value
+                .write(buf)
+                .with_context(|| format!("failed to write metadata entry {index}"))?;
+        }
+        buf.write_u8(0xff)
+            .context("failed to write the end of the metadata")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum MetadataValue {
+    Byte(u8),
+    VarInt(i32),
+    Float(f32),
+    String(String),
+    Chat(Text),
+    OptionalChat(Option<Text>),
+    Boolean(bool),
+}
+
+impl MetadataValue {
+    pub fn type_id(&self) -> i32 {
+        match self {
+            Self::Byte(_) => 0,
+            Self::VarInt(_) => 1,
+            Self::Float(_) => 3,
+            Self::String(_) => 4,
+            Self::Chat(_) => 5,
+            Self::OptionalChat(_) => 6,
+            Self::Boolean(_) => 8,
+        }
+    }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.type_id())
+            .context("failed to write the type")?;
+        match self {
+            Self::Byte(value) => buf.write_u8(*value)?,
+            Self::VarInt(value) => buf.write_var(*value)?,
+            Self::Float(value) => buf.write_finite_f32(*value)?,
+            Self::String(value) => buf.write_str(value)?,
+            Self::Chat(value) => buf.write_json(value)?,
+            Self::OptionalChat(value) => {
+                buf.write_bool(value.is_some())?;
+                if let Some(value) = value {
+                    buf.write_json(value)?;
+                }
+            }
+            Self::Boolean(value) => buf.write_bool(*value)?,
+        }
+        Ok(())
+    }
+}
+
+pub fn floating_text(
+    entity_id: i32,
+    pos: (f64, f64, f64),
+    text: Text,
+) -> (SpawnEntity, SetEntityMetadata) {
+    const INVISIBLE: u8 = 0x20;
+    const MARKER: u8 = 0x10;
+
+    let spawn = SpawnEntity {
+        entity_id,
+        uuid: Uuid::new_v4(),
+        kind: ARMOR_STAND,
+        pos,
+        pitch: 0,
+        yaw: 0,
+        head_yaw: 0,
+        data: 0,
+        velocity: [0; 3],
+    };
+    let metadata = SetEntityMetadata {
+        entity_id,
+        entries: vec![
+            (0, MetadataValue::Byte(INVISIBLE)),
+            (2, MetadataValue::OptionalChat(Some(text))),
+            (3, MetadataValue::Boolean(true)),
+            (5, MetadataValue::Boolean(true)),
+            (15, MetadataValue::Byte(MARKER)),
+        ],
+    };
+    (spawn, metadata)
+}
+
+#[derive(Clone, Debug)]
+pub struct SetEntityVelocity {
+    pub entity_id: i32,
+    pub velocity: [i16; 3],
+}
+
+impl SetEntityVelocity {
+    pub fn from_blocks_per_tick(entity_id: i32, (x, y, z): (f64, f64, f64)) -> Self {
+        Self {
+            entity_id,
+            velocity: [x, y, z].map(encode_velocity),
+        }
+    }
+}
+
+pub fn encode_velocity(blocks_per_tick: f64) -> i16 {
+    (blocks_per_tick * 8000.0).clamp(i16::MIN.into(), i16::MAX.into()) as i16
+}
+
+impl PacketFromServer for SetEntityVelocity {
+    const ID: i32 = 0x50;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.entity_id)
+            .context("failed to write the entity ID")?;
+        for (velocity, axis) in self.velocity.iter().zip(["X", "Y", "Z"]) {
+            buf.write_i16::<BigEndian>(*velocity)
+                .with_context(|| format!("failed to write the {axis} velocity"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetPassengers<'a> {
+    pub vehicle: i32,
+    pub passengers: &'a [i32],
+}
+
+impl PacketFromServer for SetPassengers<'_> {
+    const ID: i32 = 0x55;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.vehicle)
+            .context("failed to write the vehicle entity ID")?;
+        buf.write_prefixed_array(self.passengers, |buf, passenger| {
+            buf.write_var(*passenger)
+                .context("failed to write the passenger entity ID")
+        })
+        .context("failed to write the passengers")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdateAttributes {
+    pub entity_id: i32,
+    pub attributes: Vec<EntityAttribute>,
+}
+
+impl PacketFromServer for UpdateAttributes {
+    const ID: i32 = 0x66;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.entity_id)
+            .context("failed to write the entity ID")?;
+        buf.write_prefixed_array(&self.attributes, |buf, attribute| {
+            attribute
+                .write(buf)
+                .with_context(|| format!("failed to write the {} attribute", attribute.key))
+        })
+        .context("failed to write the attributes")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EntityAttribute {
+    pub key: Identifier,
+    pub base: f64,
+    pub modifiers: Vec<AttributeModifier>,
+}
+
+impl EntityAttribute {
+    pub fn movement_speed(speed: f64) -> Self {
+        Self {
+            key: MOVEMENT_SPEED,
+            base: speed,
+            modifiers: vec![],
+        }
+    }
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_identifier(&self.key)
+            .context("failed to write the key")?;
+        buf.write_finite_f64(self.base)
+            .context("failed to write the base value")?;
+        buf.write_prefixed_array(&self.modifiers, |buf, modifier| {
+            buf.write_uuid(&modifier.uuid)
+                .context("failed to write the modifier UUID")?;
+            buf.write_finite_f64(modifier.amount)
+                .context("failed to write the modifier amount")?;
+            buf.write_u8(modifier.operation as u8)
+                .context("failed to write the modifier operation")
+        })
+        .context("failed to write the modifiers")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AttributeModifier {
+    pub uuid: Uuid,
+    pub amount: f64,
+    pub operation: AttributeOperation,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AttributeOperation {
+    Add = 0,
+    AddPercent = 1,
+    Multiply = 2,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::packet_io::PacketReadExt;
+    use byteorder::ReadBytesExt;
+
+    #[test]
+    fn floating_text_metadata() -> Result<()> {
+        let (spawn, metadata) = floating_text(7, (0.5, 64.0, 0.5), "Hello".into());
+        assert_eq!(ARMOR_STAND, spawn.kind);
+
+        let mut buf = vec![];
+        metadata.write(&mut buf)?;
+        let mut buf = &buf[..];
+        assert_eq!(7, buf.read_var::<i32>()?);
+        // Invisible
+        assert_eq!(
+            [0, 0, 0x20],
+            [buf.read_u8()?, buf.read_u8()?, buf.read_u8()?]
+        );
+        // Custom name
+        assert_eq!([2, 6, 1], [buf.read_u8()?, buf.read_u8()?, buf.read_u8()?]);
+        assert_eq!("\"Hello\"", buf.read_string()?);
+        // Custom name visible
+        assert_eq!([3, 8, 1], [buf.read_u8()?, buf.read_u8()?, buf.read_u8()?]);
+        // No gravity
+        assert_eq!([5, 8, 1], [buf.read_u8()?, buf.read_u8()?, buf.read_u8()?]);
+        // Marker
+        assert_eq!(
+            [15, 0, 0x10],
+            [buf.read_u8()?, buf.read_u8()?, buf.read_u8()?]
+        );
+        assert_eq!([0xff], buf);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_velocity_in_range() {
+        assert_eq!(0, encode_velocity(0.0));
+        assert_eq!(4000, encode_velocity(0.5));
+        assert_eq!(-8000, encode_velocity(-1.0));
+    }
+
+    #[test]
+    fn encode_velocity_at_clamp_boundary() {
+        assert_eq!(i16::MAX, encode_velocity(f64::from(i16::MAX) / 8000.0));
+        assert_eq!(i16::MAX, encode_velocity(5.0));
+        assert_eq!(i16::MIN, encode_velocity(f64::from(i16::MIN) / 8000.0));
+        assert_eq!(i16::MIN, encode_velocity(-5.0));
+    }
+
+    #[test]
+    fn write_velocity() -> Result<()> {
+        let packet = SetEntityVelocity::from_blocks_per_tick(3, (0.5, -1.0, 10.0));
+        let mut buf = vec![];
+        packet.write(&mut buf)?;
+        assert_eq!([3, 0x0f, 0xa0, 0xe0, 0xc0, 0x7f, 0xff], &buf[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_movement_speed() -> Result<()> {
+        let packet = UpdateAttributes {
+            entity_id: 7,
+            attributes: vec![EntityAttribute::movement_speed(0.2)],
+        };
+        let mut buf = vec![];
+        packet.write(&mut buf)?;
+
+        let mut buf = &buf[..];
+        assert_eq!(7, buf.read_var::<i32>()?);
+        assert_eq!(1, buf.read_var::<i32>()?);
+        assert_eq!(MOVEMENT_SPEED, buf.read_identifier()?);
+        assert_eq!(0.2, buf.read_f64::<BigEndian>()?);
+        assert_eq!(0, buf.read_var::<i32>()?);
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn write_passengers() -> Result<()> {
+        let mut buf = vec![];
+        SetPassengers {
+            vehicle: 3,
+            passengers: &[1, 300],
+        }
+        .write(&mut buf)?;
+        assert_eq!(vec![3, 2, 1, 0xac, 0x02], buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use crate::mc::text::Text;
+use anyhow::Context;
+use anyhow::Result;
+use num_enum::IntoPrimitive;
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+pub struct ServerLinks<'a> {
+    pub links: &'a [ServerLink],
+}
+
+impl ServerLinks<'_> {
+    // Server links were added in 1.21
+    pub const MIN_PROTOCOL_VERSION: i32 = 767;
+}
+
+impl PacketFromServer for ServerLinks<'_> {
+    const ID: i32 = 0x7b;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_prefixed_array(self.links, |buf, link| {
+            link.write(buf).context("failed to write a link")
+        })
+        .context("failed to write the links")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerLink {
+    pub label: ServerLinkLabel,
+    pub url: String,
+}
+
+impl ServerLink {
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        match &self.label {
+            ServerLinkLabel::BuiltIn(label) => {
+                buf.write_bool(true)
+                    .context("failed to write the built-in indicator")?;
+                buf.write_var::<i32>((*label).into())
+                    .context("failed to write the built-in label")?;
+            }
+            ServerLinkLabel::Custom(label) => {
+                buf.write_bool(false)
+                    .context("failed to write the built-in indicator")?;
+                buf.write_network_nbt(&label.clone().into_full())
+                    .context("failed to write the custom label")?;
+            }
+        }
+        buf.write_str(&self.url).context("failed to write the URL")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ServerLinkLabel {
+    BuiltIn(BuiltInServerLink),
+    Custom(Text),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, IntoPrimitive)]
+#[repr(i32)]
+pub enum BuiltInServerLink {
+    BugReport,
+    CommunityGuidelines,
+    Support,
+    Status,
+    Feedback,
+    Community,
+    Website,
+    Forums,
+    News,
+    Announcements,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::packet_io::PacketReadExt;
+    use crate::mc::text::NamedTextColor;
+    use byteorder::ReadBytesExt;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    #[test]
+    fn write_built_in_and_custom_links() -> Result<()> {
+        let links = [
+            ServerLink {
+                label: ServerLinkLabel::BuiltIn(BuiltInServerLink::Website),
+                url: "https://mastodon.social".into(),
+            },
+            ServerLink {
+                label: ServerLinkLabel::Custom(Text::from("Rules").color(NamedTextColor::Red)),
+                url: "https://mastodon.social/about".into(),
+            },
+        ];
+        let mut buf = vec![];
+        ServerLinks { links: &links }.write(&mut buf)?;
+
+        let mut buf = &buf[..];
+        assert_eq!(2, buf.read_var::<i32>()?);
+        assert!(buf.read_bool()?);
+        assert_eq!(6, buf.read_var::<i32>()?);
+        assert_eq!("https://mastodon.social", buf.read_string()?);
+
+        assert!(!buf.read_bool()?);
+        // Network NBT omits the root name, so add an empty one back for fastnbt
+        assert_eq!(0x0a, buf.read_u8()?);
+        let label: HashMap<String, String> = (&[0x0a, 0, 0][..]).chain(&mut buf).read_nbt()?;
+        assert_eq!("Rules", label["text"]);
+        assert_eq!("red", label["color"]);
+        assert_eq!("https://mastodon.social/about", buf.read_string()?);
+        assert!(buf.is_empty());
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@ use anyhow::Result;
 use byteorder::{BigEndian, WriteBytesExt};
 use std::io::Write;
 
+#[derive(Clone, Debug)]
 pub struct SyncPlayerPos {
     pub x: f64,
     pub y: f64,
@@ -18,20 +19,18 @@ pub struct SyncPlayerPos {
 }
 
 impl PacketFromServer for SyncPlayerPos {
-    fn id() -> i32 {
-        0x38
-    }
+    const ID: i32 = 0x38;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
-        buf.write_f64::<BigEndian>(self.x)
+        buf.write_finite_f64(self.x)
             .context("failed to write the X position")?;
-        buf.write_f64::<BigEndian>(self.y)
+        buf.write_finite_f64(self.y)
             .context("failed to write the Y position")?;
-        buf.write_f64::<BigEndian>(self.z)
+        buf.write_finite_f64(self.z)
             .context("failed to write the Z position")?;
-        buf.write_f32::<BigEndian>(self.yaw)
+        buf.write_finite_f32(self.yaw)
             .context("failed to write the yaw")?;
-        buf.write_f32::<BigEndian>(self.pitch)
+        buf.write_finite_f32(self.pitch)
             .context("failed to write the pitch")?;
         buf.write_u8(self.flags)
             .context("failed to write the flags")?;
@@ -42,15 +41,14 @@ impl PacketFromServer for SyncPlayerPos {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct SetSpawnPos {
     pub pos: BlockPos,
     pub angle: f32,
 }
 
 impl PacketFromServer for SetSpawnPos {
-    fn id() -> i32 {
-        0x4c
-    }
+    const ID: i32 = 0x4c;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_block_pos(&self.pos)
@@ -59,3 +57,43 @@ impl PacketFromServer for SetSpawnPos {
             .context("failed to write the angle")
     }
 }
+
+#[derive(Clone, Debug)]
+pub struct SetHealth {
+    pub health: f32,
+    pub food: i32,
+    pub saturation: f32,
+}
+
+impl PacketFromServer for SetHealth {
+    const ID: i32 = 0x53;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_finite_f32(self.health)
+            .context("failed to write the health")?;
+        buf.write_var(self.food)
+            .context("failed to write the food level")?;
+        buf.write_finite_f32(self.saturation)
+            .context("failed to write the saturation")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerAbilities {
+    pub flags: u8,
+    pub flying_speed: f32,
+    pub fov_modifier: f32,
+}
+
+impl PacketFromServer for PlayerAbilities {
+    const ID: i32 = 0x30;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_u8(self.flags)
+            .context("failed to write the flags")?;
+        buf.write_finite_f32(self.flying_speed)
+            .context("failed to write the flying speed")?;
+        buf.write_finite_f32(self.fov_modifier)
+            .context("failed to write the FOV modifier")
+    }
+}
@@ -0,0 +1,30 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use anyhow::Context;
+use anyhow::Result;
+use std::io::Write;
+
+// Recipes aren't supported yet, so this always declares zero of them
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UpdateRecipes;
+
+impl PacketFromServer for UpdateRecipes {
+    const ID: i32 = 0x69;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(0).context("failed to write the recipe count")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_empty_recipes() -> Result<()> {
+        let mut buf = vec![];
+        UpdateRecipes.write(&mut buf)?;
+        assert_eq!(vec![0], buf);
+        Ok(())
+    }
+}
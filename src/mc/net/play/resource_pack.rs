@@ -0,0 +1,110 @@
+use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+use crate::mc::net::{Connection, PacketFromClient, PacketFromServer};
+use crate::mc::text::Text;
+use crate::server::{ConnectionAction, Server};
+use anyhow::{Context, Result};
+use num_enum::TryFromPrimitive;
+use std::io::{Read, Write};
+
+#[derive(Clone, Debug)]
+pub struct ResourcePack {
+    pub url: String,
+    pub hash: String,
+    pub forced: bool,
+    pub prompt: Option<Text>,
+}
+
+impl PacketFromServer for ResourcePack {
+    const ID: i32 = 0x3c;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_str(&self.url)
+            .context("failed to write the URL")?;
+        buf.write_str(&self.hash)
+            .context("failed to write the hash")?;
+        buf.write_bool(self.forced)
+            .context("failed to write the forced indicator")?;
+
+        buf.write_bool(self.prompt.is_some())
+            .context("failed to write the prompt indicator")?;
+        if let Some(prompt) = &self.prompt {
+            buf.write_json(prompt)
+                .context("failed to write the prompt")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ResourcePackResponse {
+    pub result: ResourcePackResult,
+}
+
+impl PacketFromClient for ResourcePackResponse {
+    const ID: i32 = 0x24;
+
+    fn read<R: Read>(buf: &mut R) -> Result<Self> {
+        let result = buf.read_enum().context("failed to read the result")?;
+        Ok(Self { result })
+    }
+
+    fn handle(
+        self: Box<Self>,
+        connection: &mut Connection,
+        server: &Server,
+    ) -> Result<ConnectionAction> {
+        let declined_forced =
+            self.result == ResourcePackResult::Declined && connection.resource_pack_forced;
+        if declined_forced && server.config().kick_on_declined_forced_resource_pack {
+            connection
+                .send_kick(Text::from("You must accept the resource pack"))
+                .context("failed to kick the player for declining the resource pack")?;
+            return Ok(ConnectionAction::Close);
+        }
+        Ok(ConnectionAction::DoNothing)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, TryFromPrimitive)]
+#[repr(i32)]
+pub enum ResourcePackResult {
+    Loaded = 0,
+    Declined = 1,
+    FailedDownload = 2,
+    Accepted = 3,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::play::PlayDisconnect;
+    use crate::mc::net::{play, test_util};
+    use crate::server::ServerConfig;
+
+    #[test]
+    fn declined_forced_pack_kicks() -> Result<()> {
+        let (mut connection, mut client) = test_util::play_connection();
+        let server = Server::bind_with_config("127.0.0.1:0", ServerConfig::default())?;
+        connection.send_resource_pack(ResourcePack {
+            url: "https://example.com/pack.zip".into(),
+            hash: String::new(),
+            forced: true,
+            prompt: None,
+        })?;
+        assert_eq!(
+            ResourcePack::ID,
+            test_util::read_packet(&mut client, false)?.0
+        );
+
+        let body = [ResourcePackResult::Declined as u8];
+        let packet = play::decode(ResourcePackResponse::ID, &mut &body[..])?.unwrap();
+        let action = packet.handle(&mut connection, &server)?;
+        assert!(matches!(action, ConnectionAction::Close));
+        assert_eq!(
+            PlayDisconnect::ID,
+            test_util::read_packet(&mut client, false)?.0
+        );
+        Ok(())
+    }
+}
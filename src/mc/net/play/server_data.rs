@@ -0,0 +1,107 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use crate::mc::text::Text;
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+pub struct ServerData<'a> {
+    pub motd: Option<&'a Text>,
+    // The icon is the same data URI that the listing uses
+    pub icon: Option<&'a str>,
+    pub enforces_secure_chat: bool,
+    pub protocol_version: i32,
+}
+
+impl ServerData<'_> {
+    // Since 1.19.4, the MOTD is required and the icon is sent as raw PNG bytes
+    pub const BYTES_ICON_PROTOCOL_VERSION: i32 = 762;
+}
+
+impl PacketFromServer for ServerData<'_> {
+    const ID: i32 = 0x41;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        if self.protocol_version >= Self::BYTES_ICON_PROTOCOL_VERSION {
+            let empty = Text::from("");
+            buf.write_json(self.motd.unwrap_or(&empty))
+                .context("failed to write the MOTD")?;
+            let icon = self
+                .icon
+                .map(decode_icon)
+                .transpose()
+                .context("failed to decode the icon")?;
+            buf.write_optional(&icon, |buf, icon| buf.write_byte_array(icon))
+                .context("failed to write the icon")?;
+        } else {
+            buf.write_optional(&self.motd, |buf, motd| buf.write_json(motd))
+                .context("failed to write the MOTD")?;
+            buf.write_optional(&self.icon, |buf, icon| buf.write_str(icon))
+                .context("failed to write the icon")?;
+        }
+        buf.write_bool(self.enforces_secure_chat)
+            .context("failed to write whether secure chat is enforced")
+    }
+}
+
+fn decode_icon(icon: &str) -> Result<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let encoded = icon.strip_prefix("data:image/png;base64,").unwrap_or(icon);
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut bits = 0_u32;
+    let mut bit_count = 0;
+    for char in encoded.bytes().filter(|char| !char.is_ascii_whitespace()) {
+        if char == b'=' {
+            break;
+        }
+        let Some(value) = ALPHABET.iter().position(|c| *c == char) else {
+            bail!("{:?} isn't a base64 character", char as char);
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::packet_io::PacketReadExt;
+
+    #[test]
+    fn write_server_data() -> Result<()> {
+        let motd = Text::from("Minestodon");
+        let mut data = ServerData {
+            motd: Some(&motd),
+            icon: Some("data:image/png;base64,iVBORw=="),
+            enforces_secure_chat: false,
+            protocol_version: 761,
+        };
+
+        let mut buf = vec![];
+        data.write(&mut buf)?;
+        let mut body = &buf[..];
+        assert!(body.read_bool()?);
+        assert_eq!("\"Minestodon\"", body.read_string()?);
+        assert!(body.read_bool()?);
+        assert_eq!("data:image/png;base64,iVBORw==", body.read_string()?);
+        assert!(!body.read_bool()?);
+        assert!(body.is_empty());
+
+        data.protocol_version = ServerData::BYTES_ICON_PROTOCOL_VERSION;
+        let mut buf = vec![];
+        data.write(&mut buf)?;
+        let mut body = &buf[..];
+        assert_eq!("\"Minestodon\"", body.read_string()?);
+        assert!(body.read_bool()?);
+        assert_eq!(b"\x89PNG", &body.read_byte_array()?[..]);
+        assert!(!body.read_bool()?);
+        assert!(body.is_empty());
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 use crate::mc::net::packet_io::PacketWriteExt;
 use crate::mc::net::play::player::SetSpawnPos;
+use crate::mc::net::play::recipe::UpdateRecipes;
+use crate::mc::net::play::tag::UpdateTags;
+use crate::mc::net::play::world::{ChunkData, SetCenterChunk};
 use crate::mc::net::play::PluginMessageFromServer;
 use crate::mc::net::{Connection, PacketFromServer};
 use crate::mc::player::GameMode;
@@ -10,11 +13,10 @@ use crate::server::Server;
 use anyhow::Context;
 use anyhow::Result;
 use byteorder::{BigEndian, WriteBytesExt};
-use minestodon_macros::minestodon;
 use serde::Serialize;
 use std::io::Write;
 
-#[derive(Serialize)]
+#[derive(Copy, Clone, Debug, Serialize)]
 pub struct Registries<'a> {
     #[serde(rename = "minecraft:worldgen/biome")]
     pub biome: &'a Registry<Biome>,
@@ -24,6 +26,7 @@ pub struct Registries<'a> {
     pub dimension_type: &'a Registry<DimensionType>,
 }
 
+#[derive(Clone, Debug)]
 pub struct PlayLogin<'a> {
     pub entity_id: i32,
     pub hardcore: bool,
@@ -45,9 +48,7 @@ pub struct PlayLogin<'a> {
 }
 
 impl PacketFromServer for PlayLogin<'_> {
-    fn id() -> i32 {
-        0x24
-    }
+    const ID: i32 = 0x24;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_i32::<BigEndian>(self.entity_id)
@@ -59,17 +60,11 @@ impl PacketFromServer for PlayLogin<'_> {
         buf.write_i8(self.last_game_mode.map(GameMode::into).unwrap_or(-1))
             .context("failed to write the last game mode")?;
 
-        let world_len = self
-            .worlds
-            .len()
-            .try_into()
-            .context("the world count doesn't fit in an i32")?;
-        buf.write_var::<i32>(world_len)
-            .context("failed to write the world count")?;
-        for dimension in &self.worlds {
-            buf.write_identifier(dimension)
-                .context("failed to write the world")?;
-        }
+        buf.write_prefixed_array(&self.worlds, |buf, world| {
+            buf.write_identifier(world)
+                .context("failed to write the world")
+        })
+        .context("failed to write the worlds")?;
 
         buf.write_nbt(&self.registries)
             .context("failed to write the registries")?;
@@ -107,30 +102,37 @@ impl PacketFromServer for PlayLogin<'_> {
     }
 }
 
-pub fn set_up(connection: &mut Connection, server: &Server) -> Result<()> {
+pub fn play_login(server: &Server) -> Result<PlayLogin<'static>> {
     let login = PlayLogin {
-        entity_id: server.next_entity_id(),
-        hardcore: false,
-        game_mode: GameMode::Adventure,
-        last_game_mode: None,
-        worlds: vec![minestodon!("world")],
+        entity_id: server
+            .next_entity_id()
+            .context("failed to assign the player's entity ID")?,
+        hardcore: server.config().hardcore,
+        game_mode: server.config().game_mode,
+        last_game_mode: server.config().last_game_mode,
+        worlds: world::WORLDS.into_iter().map(|world| world.id).collect(),
         registries: Registries {
             biome: &registry::BIOMES,
             message_type: &registry::MESSAGE_TYPES,
             dimension_type: &registry::DIMENSION_TYPES,
         },
-        dimension_type: world::DIMENSION_TYPE,
-        world: minestodon!("world"),
+        dimension_type: world::TIMELINE_WORLD.dimension_type,
+        world: world::TIMELINE_WORLD.id,
         hashed_seed: 0,
-        max_players: 0,
-        view_distance: 32,
+        max_players: server.config().max_players,
+        view_distance: server.config().clamped_view_distance(),
         simulation_distance: 32,
-        reduced_debug_info: false,
-        respawn_screen: true,
-        debug_mode: false,
-        flat_world: true,
+        reduced_debug_info: server.config().reduced_debug_info,
+        respawn_screen: server.config().respawn_screen,
+        debug_mode: server.config().debug_mode,
+        flat_world: server.config().flat_world,
         death_pos: None,
     };
+    Ok(login)
+}
+
+pub fn set_up(connection: &mut Connection, server: &Server) -> Result<()> {
+    let login = play_login(server)?;
     connection
         .send_packet(login)
         .context("failed to send the login packet")?;
@@ -141,11 +143,308 @@ pub fn set_up(connection: &mut Connection, server: &Server) -> Result<()> {
         .send_packet(brand)
         .context("failed to send the server brand")?;
 
+    let listing = server.listing();
+    connection
+        .send_server_data(Some(&listing.motd), listing.icon.as_deref(), false)
+        .context("failed to send the server data")?;
+
+    connection
+        .send_packet(UpdateRecipes)
+        .context("failed to send the recipes")?;
+    connection
+        .send_packet(UpdateTags::minimal())
+        .context("failed to send the tags")?;
+
     let set_spawn = SetSpawnPos {
-        pos: BlockPos::new(0, 0, 0),
-        angle: 0.0,
+        pos: server.config().spawn_pos,
+        angle: server.config().spawn_angle,
     };
     connection
         .send_packet(set_spawn)
-        .context("failed to set the spawn position")
+        .context("failed to set the spawn position")?;
+
+    send_spawn_chunks(connection, server).context("failed to send the spawn chunks")
+}
+
+// The fediverse dimension type is 384 blocks tall
+const SECTION_COUNT: usize = 384 / 16;
+
+pub fn send_spawn_chunks(connection: &mut Connection, server: &Server) -> Result<()> {
+    let biome = registry::BIOMES
+        .id_of(&world::BIOME)
+        .context("the spawn biome isn't registered")?;
+
+    let spawn = server.config().spawn_pos;
+    let center = SetCenterChunk {
+        x: spawn.x >> 4,
+        z: spawn.z >> 4,
+    };
+    connection
+        .send_packet(center)
+        .context("failed to set the center chunk")?;
+    for chunk in chunk_grid(center, server.config().clamped_view_distance(), biome) {
+        connection
+            .send_packet(chunk)
+            .context("failed to send a chunk")?;
+    }
+    Ok(())
+}
+
+pub fn chunk_grid(
+    center: SetCenterChunk,
+    view_distance: i32,
+    biome: i32,
+) -> impl Iterator<Item = ChunkData> {
+    (-view_distance..=view_distance).flat_map(move |x| {
+        (-view_distance..=view_distance)
+            .map(move |z| ChunkData::empty(center.x + x, center.z + z, SECTION_COUNT, biome))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net;
+    use crate::mc::net::packet_io::PacketReadExt;
+    use crate::mc::net::play::server_data::ServerData;
+    use crate::mc::net::{test_util, ConnectionIo};
+    use crate::server::ServerConfig;
+    use anyhow::anyhow;
+    use byteorder::ReadBytesExt;
+    use std::io;
+    use std::io::{ErrorKind, Read};
+    use std::iter;
+    use std::time::Duration;
+
+    #[test]
+    fn debug_play_login() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let debug = format!("{:?}", play_login(&server)?);
+        assert!(debug.starts_with("PlayLogin { entity_id: 0,"));
+        assert!(debug.contains("game_mode: Adventure"));
+        Ok(())
+    }
+
+    #[test]
+    fn join_sequence() -> Result<()> {
+        registry::init();
+        let server = Server::bind("127.0.0.1:0")?;
+        let packets = test_util::capture_packets(|connection| set_up(connection, &server))?;
+        let ids = packets.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+        let chunk_count = (server.config().clamped_view_distance() * 2 + 1).pow(2);
+        let mut expected = vec![
+            PlayLogin::ID,
+            PluginMessageFromServer::ID,
+            ServerData::ID,
+            UpdateRecipes::ID,
+            UpdateTags::ID,
+            SetSpawnPos::ID,
+            SetCenterChunk::ID,
+        ];
+        expected.extend(iter::repeat_n(ChunkData::ID, chunk_count.try_into()?));
+        assert_eq!(expected, ids);
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_grid_follows_the_spawn() -> Result<()> {
+        registry::init();
+        let config = ServerConfig {
+            spawn_pos: BlockPos::new(100, 64, -20),
+            view_distance: 2,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let packets =
+            test_util::capture_packets(|connection| send_spawn_chunks(connection, &server))?;
+
+        let mut center = &packets[0].1[..];
+        assert_eq!(SetCenterChunk::ID, packets[0].0);
+        assert_eq!((6, -2), (center.read_var()?, center.read_var()?));
+        for (id, body) in &packets[1..] {
+            assert_eq!(ChunkData::ID, *id);
+            let mut body = &body[..];
+            let (x, z) = (body.read_i32::<BigEndian>()?, body.read_i32::<BigEndian>()?);
+            assert!((4..=8).contains(&x) && (-4..=0).contains(&z), "({x}, {z})");
+        }
+        assert_eq!(26, packets.len());
+        Ok(())
+    }
+
+    #[test]
+    fn join_burst_sets_the_spawn() -> Result<()> {
+        registry::init();
+        let config = ServerConfig {
+            spawn_pos: BlockPos::new(8, 64, 24),
+            spawn_angle: 90.0,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let (mut connection, mut client) = test_util::connection();
+        set_up(&mut connection, &server)?;
+        drop(connection);
+
+        let spawn = iter::from_fn(|| test_util::read_packet(&mut client, false).ok())
+            .find(|(id, _)| *id == SetSpawnPos::ID)
+            .context("the spawn position wasn't sent")?;
+        let mut body = &spawn.1[..];
+        assert_eq!(BlockPos::new(8, 64, 24), body.read_block_pos()?);
+        assert_eq!(90.0, body.read_f32::<BigEndian>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn broken_pipe_during_setup_is_a_disconnect() -> Result<()> {
+        struct FailingStream(usize);
+
+        impl Read for FailingStream {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Ok(0)
+            }
+        }
+
+        impl Write for FailingStream {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(ErrorKind::BrokenPipe.into());
+                }
+                self.0 -= 1;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl ConnectionIo for FailingStream {
+            fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        registry::init();
+        let server = Server::bind("127.0.0.1:0")?;
+        let mut connection = Connection::new(FailingStream(3));
+        let err = set_up(&mut connection, &server).unwrap_err();
+        assert!(net::is_disconnect(&err));
+        assert!(!net::is_disconnect(&anyhow!("the packet is invalid")));
+        Ok(())
+    }
+
+    #[test]
+    fn play_login_uses_configured_flags() -> Result<()> {
+        registry::init();
+        let config = ServerConfig {
+            hardcore: true,
+            reduced_debug_info: true,
+            respawn_screen: false,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let login = play_login(&server)?;
+        assert!(login.hardcore && login.reduced_debug_info && !login.respawn_screen);
+        assert!(!login.debug_mode && login.flat_world);
+
+        let mut buf = vec![];
+        login.write(&mut buf)?;
+        let written = WrittenLogin::read(&buf)?;
+        assert!(written.hardcore);
+        assert_eq!([true, false, false, true], written.flags);
+        Ok(())
+    }
+
+    #[test]
+    fn play_login_uses_configured_game_mode() -> Result<()> {
+        registry::init();
+        let config = ServerConfig {
+            game_mode: GameMode::Creative,
+            last_game_mode: Some(GameMode::Survival),
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let mut buf = vec![];
+        play_login(&server)?.write(&mut buf)?;
+        let written = WrittenLogin::read(&buf)?;
+        assert_eq!(i8::from(GameMode::Creative), written.game_mode);
+        assert_eq!(i8::from(GameMode::Survival), written.last_game_mode);
+        Ok(())
+    }
+
+    // The fields PlayLogin was written with, read back in order
+    struct WrittenLogin {
+        hardcore: bool,
+        game_mode: i8,
+        last_game_mode: i8,
+        // Reduced debug info, respawn screen, debug mode and flat world
+        flags: [bool; 4],
+    }
+
+    impl WrittenLogin {
+        fn read(mut buf: &[u8]) -> Result<Self> {
+            buf.read_i32::<BigEndian>()?;
+            let hardcore = buf.read_bool()?;
+            let game_mode = buf.read_i8()?;
+            let last_game_mode = buf.read_i8()?;
+            buf.read_prefixed_array(PacketReadExt::read_identifier)?;
+            buf.read_nbt_value()?
+                .context("the registries are missing")?;
+            buf.read_identifier()?;
+            buf.read_identifier()?;
+            buf.read_i64::<BigEndian>()?;
+            for _ in 0..3 {
+                buf.read_var::<i32>()?;
+            }
+            let flags = [
+                buf.read_bool()?,
+                buf.read_bool()?,
+                buf.read_bool()?,
+                buf.read_bool()?,
+            ];
+            assert!(!buf.read_bool()?, "a death position was written");
+            assert!(buf.is_empty(), "{} bytes were left over", buf.len());
+            Ok(Self {
+                hardcore,
+                game_mode,
+                last_game_mode,
+                flags,
+            })
+        }
+    }
+
+    #[test]
+    fn chunk_grid_matches_view_distance() {
+        let center = SetCenterChunk { x: 4, z: -4 };
+        let chunks = chunk_grid(center, 2, 0).collect::<Vec<_>>();
+        assert_eq!(25, chunks.len());
+        assert!(chunks.iter().all(|chunk| (2..=6).contains(&chunk.x)));
+        assert!(chunks.iter().all(|chunk| (-6..=-2).contains(&chunk.z)));
+    }
+
+    #[test]
+    fn play_login_lists_all_worlds() -> Result<()> {
+        registry::init();
+        let server = Server::bind("127.0.0.1:0")?;
+        let login = play_login(&server)?;
+        assert_eq!(
+            vec![world::TIMELINE_WORLD.id, world::PROFILE_WORLD.id],
+            login.worlds
+        );
+        assert_eq!(world::TIMELINE_WORLD.id, login.world);
+
+        let mut dimension_types = vec![];
+        login
+            .registries
+            .dimension_type
+            .for_each(|id, _| dimension_types.push(id.clone()));
+        assert_eq!(
+            vec![world::DIMENSION_TYPE, world::PROFILE_DIMENSION_TYPE],
+            dimension_types
+        );
+        for world in world::WORLDS {
+            assert!(dimension_types.contains(&world.dimension_type));
+        }
+        Ok(())
+    }
 }
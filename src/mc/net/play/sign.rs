@@ -0,0 +1,104 @@
+use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+use crate::mc::net::play::chat::SetActionBarText;
+use crate::mc::net::{Connection, PacketFromClient, PacketFromServer};
+use crate::mc::text::Text;
+use crate::mc::world::BlockPos;
+use crate::server::{ConnectionAction, Server};
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+
+// The client makes up a sign of its own when there isn't one at the position
+pub const TEXT_INPUT_POS: BlockPos = BlockPos::new(0, 0, 0);
+
+pub fn request_text_input(connection: &mut Connection, prompt: Text) -> Result<()> {
+    connection
+        .send_packet(SetActionBarText { text: prompt })
+        .context("failed to send the prompt")?;
+    connection
+        .send_packet(OpenSignEditor {
+            pos: TEXT_INPUT_POS,
+        })
+        .context("failed to open the sign editor")
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct OpenSignEditor {
+    pub pos: BlockPos,
+}
+
+impl PacketFromServer for OpenSignEditor {
+    const ID: i32 = 0x2d;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_block_pos(&self.pos)
+            .context("failed to write the sign position")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdateSign {
+    pub pos: BlockPos,
+    pub lines: [String; 4],
+}
+
+impl PacketFromClient for UpdateSign {
+    const ID: i32 = 0x2e;
+
+    fn read<R: Read>(buf: &mut R) -> Result<Self> {
+        let pos = buf
+            .read_block_pos()
+            .context("failed to read the sign position")?;
+        let mut lines: [String; 4] = Default::default();
+        for (index, line) in lines.iter_mut().enumerate() {
+            *line = buf
+                .read_string()
+                .with_context(|| format!("failed to read line {}", index + 1))?;
+        }
+        Ok(Self { pos, lines })
+    }
+
+    fn handle(
+        self: Box<Self>,
+        connection: &mut Connection,
+        server: &Server,
+    ) -> Result<ConnectionAction> {
+        if let Some(on_update) = server.config().on_sign_updated {
+            on_update(connection, self.pos, self.lines);
+        }
+        Ok(ConnectionAction::DoNothing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::{play, test_util};
+    use crate::server::ServerConfig;
+    use std::sync::Mutex;
+
+    #[test]
+    fn sign_update_reaches_the_callback() -> Result<()> {
+        static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        let config = ServerConfig {
+            on_sign_updated: Some(|_, pos, lines| {
+                assert_eq!(TEXT_INPUT_POS, pos);
+                *LINES.lock().unwrap() = lines.to_vec();
+            }),
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let (mut connection, _client) = test_util::connection();
+
+        let mut body = vec![];
+        body.write_block_pos(&TEXT_INPUT_POS)?;
+        for line in ["Hello", "from", "a sign", ""] {
+            body.write_str(line)?;
+        }
+        let mut slice = &body[..];
+        let packet = play::decode(UpdateSign::ID, &mut slice)?.unwrap();
+        packet.handle(&mut connection, &server)?;
+        assert!(slice.is_empty());
+        assert_eq!(vec!["Hello", "from", "a sign", ""], *LINES.lock().unwrap());
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use crate::mc::Identifier;
+use anyhow::{Context, Result};
+use byteorder::{BigEndian, WriteBytesExt};
+use num_enum::IntoPrimitive;
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+pub enum Sound {
+    Named {
+        id: Identifier,
+        fixed_range: Option<f32>,
+    },
+    Registered(i32),
+}
+
+impl Sound {
+    pub fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        match self {
+            Self::Named { id, fixed_range } => {
+                buf.write_var(0)
+                    .context("failed to write a zero to indicate a named sound")?;
+                buf.write_identifier(id)
+                    .context("failed to write the sound name")?;
+                buf.write_bool(fixed_range.is_some())
+                    .context("failed to write the fixed range indicator")?;
+                if let Some(range) = fixed_range {
+                    buf.write_f32::<BigEndian>(*range)
+                        .context("failed to write the fixed range")?;
+                }
+                Ok(())
+            }
+            Self::Registered(id) => {
+                let id = id
+                    .checked_add(1)
+                    .context("the sound ID is too large to be offset by one")?;
+                buf.write_var(id).context("failed to write the sound ID")
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, IntoPrimitive)]
+#[repr(i32)]
+pub enum SoundCategory {
+    Master,
+    Music,
+    Records,
+    Weather,
+    Blocks,
+    Hostile,
+    Neutral,
+    Players,
+    Ambient,
+    Voice,
+}
+
+#[derive(Clone, Debug)]
+pub struct SoundEffect {
+    pub sound: Sound,
+    pub category: SoundCategory,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub volume: f32,
+    pub pitch: f32,
+    pub seed: i64,
+}
+
+impl PacketFromServer for SoundEffect {
+    const ID: i32 = 0x5e;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        self.sound.write(buf).context("failed to write the sound")?;
+        buf.write_var::<i32>(self.category.into())
+            .context("failed to write the sound category")?;
+        buf.write_fixed_point_i32(self.x, 8.0)
+            .context("failed to write the X position")?;
+        buf.write_fixed_point_i32(self.y, 8.0)
+            .context("failed to write the Y position")?;
+        buf.write_fixed_point_i32(self.z, 8.0)
+            .context("failed to write the Z position")?;
+        buf.write_f32::<BigEndian>(self.volume)
+            .context("failed to write the volume")?;
+        buf.write_f32::<BigEndian>(self.pitch)
+            .context("failed to write the pitch")?;
+        buf.write_i64::<BigEndian>(self.seed)
+            .context("failed to write the seed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minestodon_macros::minestodon;
+
+    #[test]
+    fn write_named_sound() -> Result<()> {
+        let sound = Sound::Named {
+            id: minestodon!("toot"),
+            fixed_range: None,
+        };
+        let mut buf = vec![];
+        sound.write(&mut buf)?;
+
+        let mut expected = vec![0];
+        expected.write_str("minestodon:toot")?;
+        expected.push(0);
+        assert_eq!(expected, buf);
+        Ok(())
+    }
+
+    #[test]
+    fn write_registered_sound() -> Result<()> {
+        let mut buf = vec![];
+        Sound::Registered(0x7f).write(&mut buf)?;
+        assert_eq!([0x80, 0x01], &buf[..]);
+        Ok(())
+    }
+}
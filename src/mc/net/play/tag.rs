@@ -0,0 +1,165 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use crate::mc::Identifier;
+use anyhow::Context;
+use anyhow::Result;
+use minestodon_macros::minecraft;
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+pub struct UpdateTags {
+    pub registries: Vec<TagRegistry>,
+}
+
+impl UpdateTags {
+    pub fn empty() -> Self {
+        let registries = [
+            minecraft!("block"),
+            minecraft!("item"),
+            minecraft!("fluid"),
+            minecraft!("entity_type"),
+            minecraft!("game_event"),
+        ];
+        Self {
+            registries: registries
+                .into_iter()
+                .map(|registry| TagRegistry {
+                    registry,
+                    tags: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    // Fluid physics depends on the water and lava tags, the worlds have no blocks for the
+    // mining tags to contain but the client still expects them to exist
+    pub fn minimal() -> Self {
+        let mut tags = Self::empty();
+        for registry in &mut tags.registries {
+            if registry.registry == minecraft!("fluid") {
+                registry.tags = vec![
+                    Tag::new(minecraft!("water"), vec![FLOWING_WATER, WATER]),
+                    Tag::new(minecraft!("lava"), vec![FLOWING_LAVA, LAVA]),
+                ];
+            } else if registry.registry == minecraft!("block") {
+                registry.tags = [
+                    minecraft!("mineable/axe"),
+                    minecraft!("mineable/hoe"),
+                    minecraft!("mineable/pickaxe"),
+                    minecraft!("mineable/shovel"),
+                ]
+                .into_iter()
+                .map(|name| Tag::new(name, vec![]))
+                .collect();
+            }
+        }
+        tags
+    }
+}
+
+const FLOWING_WATER: i32 = 1;
+const WATER: i32 = 2;
+const FLOWING_LAVA: i32 = 3;
+const LAVA: i32 = 4;
+
+impl PacketFromServer for UpdateTags {
+    const ID: i32 = 0x6a;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        let registry_len = self
+            .registries
+            .len()
+            .try_into()
+            .context("the registry count doesn't fit in an i32")?;
+        buf.write_var::<i32>(registry_len)
+            .context("failed to write the registry count")?;
+        for registry in &self.registries {
+            registry
+                .write(buf)
+                .with_context(|| format!("failed to write the {} tags", registry.registry))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TagRegistry {
+    pub registry: Identifier,
+    pub tags: Vec<Tag>,
+}
+
+impl TagRegistry {
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_identifier(&self.registry)
+            .context("failed to write the registry")?;
+        let tag_len = self
+            .tags
+            .len()
+            .try_into()
+            .context("the tag count doesn't fit in an i32")?;
+        buf.write_var::<i32>(tag_len)
+            .context("failed to write the tag count")?;
+        for tag in &self.tags {
+            buf.write_identifier(&tag.name)
+                .context("failed to write the tag name")?;
+            let entry_len = tag
+                .entries
+                .len()
+                .try_into()
+                .context("the entry count doesn't fit in an i32")?;
+            buf.write_var::<i32>(entry_len)
+                .context("failed to write the entry count")?;
+            for &entry in &tag.entries {
+                buf.write_var(entry).context("failed to write an entry")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub name: Identifier,
+    pub entries: Vec<i32>,
+}
+
+impl Tag {
+    pub fn new(name: Identifier, entries: Vec<i32>) -> Self {
+        Self { name, entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::packet_io::PacketReadExt;
+
+    #[test]
+    fn write_fluid_tags() -> Result<()> {
+        let fluids = UpdateTags::minimal()
+            .registries
+            .into_iter()
+            .find(|registry| registry.registry == minecraft!("fluid"))
+            .context("the fluid tags are missing")?;
+        let mut buf = vec![];
+        fluids.write(&mut buf)?;
+
+        let mut body = &buf[..];
+        assert_eq!(minecraft!("fluid"), body.read_identifier()?);
+        assert_eq!(2, body.read_var::<i32>()?);
+        assert_eq!(minecraft!("water"), body.read_identifier()?);
+        assert_eq!(2, body.read_var::<i32>()?);
+        assert_eq!(
+            [FLOWING_WATER, WATER],
+            [body.read_var::<i32>()?, body.read_var()?]
+        );
+        assert_eq!(minecraft!("lava"), body.read_identifier()?);
+        assert_eq!(2, body.read_var::<i32>()?);
+        assert_eq!(
+            [FLOWING_LAVA, LAVA],
+            [body.read_var::<i32>()?, body.read_var()?]
+        );
+        assert!(body.is_empty());
+        Ok(())
+    }
+}
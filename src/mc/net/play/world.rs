@@ -0,0 +1,409 @@
+use crate::mc::net::packet_io::PacketWriteExt;
+use crate::mc::net::PacketFromServer;
+use crate::mc::player::GameMode;
+use crate::mc::text::{JsonStringType, Text};
+use crate::mc::world::BlockPos;
+use crate::mc::Identifier;
+use anyhow::{Context, Result};
+use byteorder::{BigEndian, WriteBytesExt};
+use fastnbt::{LongArray, Value};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+pub struct Respawn {
+    pub dimension_type: Identifier,
+    pub world: Identifier,
+    pub hashed_seed: i64,
+    pub game_mode: GameMode,
+    pub last_game_mode: Option<GameMode>,
+    pub debug_mode: bool,
+    pub flat_world: bool,
+    pub copy_metadata: bool,
+    pub death_pos: Option<(Identifier, BlockPos)>,
+}
+
+impl PacketFromServer for Respawn {
+    const ID: i32 = 0x3d;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_identifier(&self.dimension_type)
+            .context("failed to write the dimension type")?;
+        buf.write_identifier(&self.world)
+            .context("failed to write the world")?;
+        buf.write_i64::<BigEndian>(self.hashed_seed)
+            .context("failed to write the hashed seed")?;
+        buf.write_i8(self.game_mode.into())
+            .context("failed to write the game mode")?;
+        buf.write_i8(self.last_game_mode.map(GameMode::into).unwrap_or(-1))
+            .context("failed to write the last game mode")?;
+        buf.write_bool(self.debug_mode)
+            .context("failed to write the debug mode indicator")?;
+        buf.write_bool(self.flat_world)
+            .context("failed to write the flat world indicator")?;
+        buf.write_bool(self.copy_metadata)
+            .context("failed to write the metadata copy indicator")?;
+
+        buf.write_bool(self.death_pos.is_some())
+            .context("failed to write the death position indicator")?;
+        if let Some((dimension, pos)) = &self.death_pos {
+            buf.write_identifier(dimension)
+                .context("failed to write the death dimension")?;
+            buf.write_block_pos(pos)
+                .context("failed to write the death position")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SetCenterChunk {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl PacketFromServer for SetCenterChunk {
+    const ID: i32 = 0x4a;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.x)
+            .context("failed to write the chunk X coordinate")?;
+        buf.write_var(self.z)
+            .context("failed to write the chunk Z coordinate")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChunkData {
+    pub x: i32,
+    pub z: i32,
+    pub section_count: usize,
+    pub biome: i32,
+}
+
+impl ChunkData {
+    pub fn empty(x: i32, z: i32, section_count: usize, biome: i32) -> Self {
+        Self {
+            x,
+            z,
+            section_count,
+            biome,
+        }
+    }
+
+    fn write_sections<W: Write>(&self, buf: &mut W) -> Result<()> {
+        for _ in 0..self.section_count {
+            buf.write_i16::<BigEndian>(0)
+                .context("failed to write the non-air block count")?;
+
+            // Single-valued paletted containers with no data: all air, all one biome
+            buf.write_u8(0)
+                .context("failed to write the block bits per entry")?;
+            buf.write_var(0).context("failed to write the block")?;
+            buf.write_var(0)
+                .context("failed to write the block data length")?;
+            write_single_biome(buf, self.biome)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_single_biome<W: Write>(buf: &mut W, biome: i32) -> Result<()> {
+    buf.write_u8(0)
+        .context("failed to write the biome bits per entry")?;
+    buf.write_var(biome).context("failed to write the biome")?;
+    buf.write_var(0)
+        .context("failed to write the biome data length")
+}
+
+#[derive(Serialize)]
+struct Heightmaps {
+    #[serde(rename = "MOTION_BLOCKING")]
+    motion_blocking: LongArray,
+}
+
+impl PacketFromServer for ChunkData {
+    const ID: i32 = 0x20;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_i32::<BigEndian>(self.x)
+            .context("failed to write the chunk X coordinate")?;
+        buf.write_i32::<BigEndian>(self.z)
+            .context("failed to write the chunk Z coordinate")?;
+
+        // 256 9-bit entries packed 7 per long, which covers a 384-block-tall world
+        let heightmaps = Heightmaps {
+            motion_blocking: LongArray::new(vec![0; 37]),
+        };
+        buf.write_nbt(&heightmaps)
+            .context("failed to write the heightmaps")?;
+
+        let mut sections = vec![];
+        self.write_sections(&mut sections)
+            .context("failed to write the chunk sections")?;
+        let sections_len = sections
+            .len()
+            .try_into()
+            .context("the chunk data length doesn't fit in an i32")?;
+        buf.write_var::<i32>(sections_len)
+            .context("failed to write the chunk data length")?;
+        buf.write_all(&sections)
+            .context("failed to write the chunk data")?;
+
+        buf.write_var(0)
+            .context("failed to write the block entity count")?;
+
+        buf.write_bool(true)
+            .context("failed to write the trust edges indicator")?;
+        for mask in [
+            "sky light",
+            "block light",
+            "empty sky light",
+            "empty block light",
+        ] {
+            buf.write_var(0)
+                .with_context(|| format!("failed to write the {mask} mask"))?;
+        }
+        buf.write_var(0)
+            .context("failed to write the sky light array count")?;
+        buf.write_var(0)
+            .context("failed to write the block light array count")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChunkBiomes {
+    pub chunks: Vec<ChunkBiomeData>,
+}
+
+impl ChunkBiomes {
+    // Chunk biome updates were added in 1.19.4
+    pub const MIN_PROTOCOL_VERSION: i32 = 762;
+}
+
+impl PacketFromServer for ChunkBiomes {
+    const ID: i32 = 0x0d;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        let len = self
+            .chunks
+            .len()
+            .try_into()
+            .context("there are too many chunks")?;
+        buf.write_var::<i32>(len)
+            .context("failed to write the chunk count")?;
+        for chunk in &self.chunks {
+            let pos = (i64::from(chunk.z) << 32) | (i64::from(chunk.x) & 0xffffffff);
+            buf.write_i64::<BigEndian>(pos)
+                .context("failed to write the chunk position")?;
+            buf.write_byte_array(&chunk.data)
+                .context("failed to write the biome data")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChunkBiomeData {
+    pub x: i32,
+    pub z: i32,
+    pub data: Vec<u8>,
+}
+
+impl ChunkBiomeData {
+    pub fn single(x: i32, z: i32, section_count: usize, biome: i32) -> Result<Self> {
+        let mut data = vec![];
+        for _ in 0..section_count {
+            write_single_biome(&mut data, biome)?;
+        }
+        Ok(Self { x, z, data })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SetBorderWarningDelay {
+    pub seconds: i32,
+}
+
+impl PacketFromServer for SetBorderWarningDelay {
+    const ID: i32 = 0x46;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.seconds)
+            .context("failed to write the warning delay")
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SetBorderWarningDistance {
+    pub blocks: i32,
+}
+
+impl PacketFromServer for SetBorderWarningDistance {
+    const ID: i32 = 0x47;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.blocks)
+            .context("failed to write the warning distance")
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SetRenderDistance {
+    pub chunks: i32,
+}
+
+impl PacketFromServer for SetRenderDistance {
+    const ID: i32 = 0x4b;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.chunks)
+            .context("failed to write the render distance")
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SetSimulationDistance {
+    pub chunks: i32,
+}
+
+impl PacketFromServer for SetSimulationDistance {
+    const ID: i32 = 0x58;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_var(self.chunks)
+            .context("failed to write the simulation distance")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockEntityData {
+    pub pos: BlockPos,
+    pub block_entity_type: i32,
+    pub data: Value,
+}
+
+impl BlockEntityData {
+    pub const SIGN_TYPE: i32 = 7;
+    pub const SKULL_TYPE: i32 = 15;
+
+    pub fn sign_text(pos: BlockPos, lines: &[Text; 4]) -> Result<Self> {
+        let mut data = HashMap::new();
+        for (index, line) in lines.iter().enumerate() {
+            let json = line
+                .to_json_string(JsonStringType::Short)
+                .with_context(|| format!("failed to serialize line {}", index + 1))?;
+            data.insert(format!("Text{}", index + 1), Value::String(json));
+        }
+        let packet = Self {
+            pos,
+            block_entity_type: Self::SIGN_TYPE,
+            data: Value::Compound(data),
+        };
+        Ok(packet)
+    }
+
+    pub fn skull_owner(pos: BlockPos, name: impl Into<String>) -> Self {
+        let owner = HashMap::from([("Name".to_string(), Value::String(name.into()))]);
+        let data = HashMap::from([("SkullOwner".to_string(), Value::Compound(owner))]);
+        Self {
+            pos,
+            block_entity_type: Self::SKULL_TYPE,
+            data: Value::Compound(data),
+        }
+    }
+}
+
+impl PacketFromServer for BlockEntityData {
+    const ID: i32 = 0x07;
+
+    fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
+        buf.write_block_pos(&self.pos)
+            .context("failed to write the position")?;
+        buf.write_var(self.block_entity_type)
+            .context("failed to write the block entity type")?;
+        buf.write_nbt(&self.data)
+            .context("failed to write the block entity data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::packet_io::PacketReadExt;
+    use byteorder::ReadBytesExt;
+
+    #[test]
+    fn write_chunk_biomes() -> Result<()> {
+        let chunk = ChunkBiomeData::single(-1, 2, 2, 5)?;
+        let mut buf = vec![];
+        ChunkBiomes {
+            chunks: vec![chunk],
+        }
+        .write(&mut buf)?;
+
+        let mut body = &buf[..];
+        assert_eq!(1, body.read_var::<i32>()?);
+        assert_eq!(2, body.read_i32::<BigEndian>()?);
+        assert_eq!(-1, body.read_i32::<BigEndian>()?);
+        assert_eq!(vec![0, 5, 0, 0, 5, 0], body.read_byte_array()?);
+        assert!(body.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn write_border_warning_delay() -> Result<()> {
+        let mut buf = vec![];
+        SetBorderWarningDelay { seconds: 15 }.write(&mut buf)?;
+        assert_eq!(vec![15], buf);
+        Ok(())
+    }
+
+    #[test]
+    fn write_border_warning_distance() -> Result<()> {
+        let mut buf = vec![];
+        SetBorderWarningDistance { blocks: 200 }.write(&mut buf)?;
+        assert_eq!(vec![0xc8, 0x01], buf);
+        Ok(())
+    }
+
+    #[test]
+    fn write_simulation_distance() -> Result<()> {
+        let mut buf = vec![];
+        SetSimulationDistance { chunks: 2 }.write(&mut buf)?;
+        assert_eq!(vec![2], buf);
+        Ok(())
+    }
+
+    #[test]
+    fn write_sign_text() -> Result<()> {
+        let lines = [
+            Text::from("Welcome"),
+            Text::from(""),
+            Text::from(""),
+            Text::from("to Minestodon"),
+        ];
+        let mut buf = vec![];
+        BlockEntityData::sign_text(BlockPos::new(1, 64, 2), &lines)?.write(&mut buf)?;
+
+        let mut body = &buf[..];
+        assert_eq!(BlockPos::new(1, 64, 2), body.read_block_pos()?);
+        assert_eq!(BlockEntityData::SIGN_TYPE, body.read_var::<i32>()?);
+        let Value::Compound(data) = fastnbt::from_bytes(body)? else {
+            panic!("the sign data isn't a compound");
+        };
+        assert_eq!(4, data.len());
+        assert_eq!(
+            Some(&Value::String("\"Welcome\"".to_string())),
+            data.get("Text1")
+        );
+        assert_eq!(Some(&Value::String("\"\"".to_string())), data.get("Text2"));
+        assert_eq!(
+            Some(&Value::String("\"to Minestodon\"".to_string())),
+            data.get("Text4")
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt, VarInt};
 use crate::mc::net::{Connection, ConnectionState, PacketFromClient, PacketFromServer};
 use crate::mc::text::Text;
 use crate::packets_from_client;
@@ -10,7 +10,7 @@ use serde::Serialize;
 use std::io::{Read, Write};
 use uuid::Uuid;
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Listing {
     pub version: ListingVersion,
     pub players: ListingPlayers,
@@ -20,7 +20,23 @@ pub struct Listing {
     pub icon: Option<String>,
 }
 
-#[derive(Serialize)]
+impl Listing {
+    pub fn minimal(motd: Text) -> Self {
+        Self {
+            version: ListingVersion::default(),
+            players: ListingPlayers::default(),
+            motd,
+            icon: None,
+        }
+    }
+
+    // The vanilla server list shows exactly two lines of the MOTD
+    pub fn motd_lines(line1: impl Into<Text>, line2: impl Into<Text>) -> Text {
+        Text::Sequential(vec!["".into(), line1.into(), "\n".into(), line2.into()])
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct ListingVersion {
     #[serde(rename = "protocol")]
     pub value: i32,
@@ -28,7 +44,16 @@ pub struct ListingVersion {
     pub name: String,
 }
 
-#[derive(Serialize)]
+impl Default for ListingVersion {
+    fn default() -> Self {
+        Self {
+            value: 761,
+            name: "Minestodon 1.19.3".into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct ListingPlayers {
     #[serde(rename = "online")]
     pub current: i32,
@@ -37,7 +62,34 @@ pub struct ListingPlayers {
     pub sample: Option<Vec<ListingPlayer>>,
 }
 
-#[derive(Serialize)]
+impl ListingPlayers {
+    pub fn sample_player(mut self, name: impl Into<String>, id: Uuid) -> Self {
+        let player = ListingPlayer {
+            name: name.into(),
+            id,
+        };
+        self.sample.get_or_insert_with(Vec::new).push(player);
+        self
+    }
+
+    // Clients show the sample as hover text, so servers often fill it with nil-UUID "players"
+    // whose names are legacy-formatted lines of text
+    pub fn info_line(self, line: &Text) -> Self {
+        self.sample_player(line.to_legacy_string(), Uuid::nil())
+    }
+}
+
+impl Default for ListingPlayers {
+    fn default() -> Self {
+        Self {
+            current: 0,
+            max: 1,
+            sample: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct ListingPlayer {
     pub name: String,
     pub id: Uuid,
@@ -45,6 +97,7 @@ pub struct ListingPlayer {
 
 packets_from_client!(decode_handshake, "handshake", [Handshake]);
 
+#[derive(Clone, Debug)]
 pub struct Handshake {
     pub version: i32,
     pub server_addr: String,
@@ -53,9 +106,7 @@ pub struct Handshake {
 }
 
 impl PacketFromClient for Handshake {
-    fn id() -> i32 {
-        0x00
-    }
+    const ID: i32 = 0x00;
 
     fn read<R: Read>(buf: &mut R) -> Result<Self> {
         let version = buf.read_var().context("failed to read the version")?;
@@ -65,11 +116,7 @@ impl PacketFromClient for Handshake {
         let server_port = buf
             .read_u16::<BigEndian>()
             .context("failed to read the server port")?;
-        let next_state = buf
-            .read_var::<i32>()
-            .context("failed to read the next state")?
-            .try_into()
-            .context("the next state is invalid, it must be 1 or 2")?;
+        let next_state = buf.read_enum().context("failed to read the next state")?;
 
         let packet = Self {
             version,
@@ -85,15 +132,19 @@ impl PacketFromClient for Handshake {
         connection: &mut Connection,
         _server: &Server,
     ) -> Result<ConnectionAction> {
-        match self.next_state {
-            NextState::Status => connection.set_state(ConnectionState::Status),
-            NextState::Login => connection.set_state(ConnectionState::Login),
-        }
+        let state = match self.next_state {
+            NextState::Status => ConnectionState::Status,
+            NextState::Login => ConnectionState::Login,
+        };
+        connection
+            .set_state(state)
+            .context("failed to switch to the next state")?;
+        connection.handshake = Some(*self);
         Ok(ConnectionAction::DoNothing)
     }
 }
 
-#[derive(TryFromPrimitive)]
+#[derive(Copy, Clone, Debug, TryFromPrimitive)]
 #[repr(i32)]
 pub enum NextState {
     Status = 1,
@@ -102,12 +153,11 @@ pub enum NextState {
 
 packets_from_client!(decode_status, "status", [StatusRequest, PingRequest]);
 
+#[derive(Debug)]
 pub struct StatusRequest;
 
 impl PacketFromClient for StatusRequest {
-    fn id() -> i32 {
-        0x00
-    }
+    const ID: i32 = 0x00;
 
     fn read<R: Read>(_buf: &mut R) -> Result<Self> {
         Ok(Self)
@@ -118,35 +168,78 @@ impl PacketFromClient for StatusRequest {
         connection: &mut Connection,
         server: &Server,
     ) -> Result<ConnectionAction> {
-        let response = StatusResponse(server.listing());
+        let json = server.status_json()?;
+        let mut body = Vec::with_capacity(i32::MAX_VAR_LEN + json.len());
+        body.write_str(&json)
+            .context("failed to write the response")?;
         connection
-            .send_packet(response)
+            .send_raw(StatusResponse::ID, &body)
             .context("failed to send a status response packet")?;
         Ok(ConnectionAction::DoNothing)
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct StatusResponse(pub Listing);
 
-impl PacketFromServer for StatusResponse {
-    fn id() -> i32 {
-        0x00
+impl StatusResponse {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(&self.0).context("failed to serialize the server listing")
+    }
+}
+
+// Listings rarely change, so the JSON is serialized once with a placeholder online count that
+// gets spliced in on every ping
+#[derive(Clone, Debug)]
+pub struct StatusTemplate {
+    before_online: String,
+    after_online: String,
+}
+
+impl StatusTemplate {
+    const PLACEHOLDER: i32 = i32::MIN;
+
+    pub fn new(listing: &Listing) -> Result<Self> {
+        let mut listing = listing.clone();
+        listing.players.current = Self::PLACEHOLDER;
+        let json = StatusResponse(listing).to_json()?;
+
+        let marker = format!("\"online\":{}", Self::PLACEHOLDER);
+        let start = json
+            .find(&marker)
+            .context("the serialized listing is missing the online count")?;
+        let (before, after) = json.split_at(start);
+        Ok(Self {
+            before_online: format!("{before}\"online\":"),
+            after_online: after[marker.len()..].to_string(),
+        })
+    }
+
+    pub fn render(&self, online: i32) -> String {
+        let mut json =
+            String::with_capacity(self.before_online.len() + self.after_online.len() + 11);
+        json.push_str(&self.before_online);
+        json.push_str(&online.to_string());
+        json.push_str(&self.after_online);
+        json
     }
+}
+
+impl PacketFromServer for StatusResponse {
+    const ID: i32 = 0x00;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
-        let serialized =
-            serde_json::to_string(&self.0).context("failed to serialize the server listing")?;
+        let serialized = self.to_json()?;
         buf.write_str(&serialized)
             .context("failed to write the response")
     }
 }
 
+#[derive(Debug)]
 pub struct PingRequest(pub i64);
 
 impl PacketFromClient for PingRequest {
-    fn id() -> i32 {
-        0x01
-    }
+    const ID: i32 = 0x01;
 
     fn read<R: Read>(buf: &mut R) -> Result<Self> {
         let payload = buf
@@ -168,15 +261,102 @@ impl PacketFromClient for PingRequest {
     }
 }
 
+#[derive(Debug)]
 pub struct PingResponse(pub i64);
 
 impl PacketFromServer for PingResponse {
-    fn id() -> i32 {
-        0x01
-    }
+    const ID: i32 = 0x01;
 
     fn write<W: Write>(&self, buf: &mut W) -> Result<()> {
         buf.write_i64::<BigEndian>(self.0)
             .context("failed to write the payload")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::test_util;
+    use crate::mc::text::NamedTextColor;
+    use serde_json::json;
+
+    #[test]
+    fn minimal_listing_json() -> Result<()> {
+        let json = StatusResponse(Listing::minimal("Hello".into())).to_json()?;
+        let expected = json!({
+            "version": { "protocol": 761, "name": "Minestodon 1.19.3" },
+            "players": { "online": 0, "max": 1 },
+            "description": "Hello",
+        });
+        assert_eq!(expected, serde_json::from_str::<serde_json::Value>(&json)?);
+        Ok(())
+    }
+
+    #[test]
+    fn two_line_motd() -> Result<()> {
+        let motd = Listing::motd_lines(
+            Text::from("Minestodon").color(NamedTextColor::Gold),
+            "Now with two lines",
+        );
+        let json = StatusResponse(Listing::minimal(motd)).to_json()?;
+        let json = serde_json::from_str::<serde_json::Value>(&json)?;
+        let expected =
+            json!(["", { "text": "Minestodon", "color": "gold" }, "\n", "Now with two lines"]);
+        assert_eq!(expected, json["description"]);
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_is_stored() -> Result<()> {
+        let mut body = vec![];
+        body.write_var(761)?;
+        body.write_str("play.example.com")?;
+        body.write_u16::<BigEndian>(25566)?;
+        body.write_var(2)?;
+
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::connection();
+        decode_handshake(Handshake::ID, &mut &body[..])?.handle(&mut connection, &server)?;
+
+        let handshake = connection
+            .handshake()
+            .context("the handshake wasn't stored")?;
+        assert_eq!("play.example.com", handshake.server_addr);
+        assert_eq!(25566, handshake.server_port);
+        assert!(matches!(handshake.next_state, NextState::Login));
+        Ok(())
+    }
+
+    #[test]
+    fn info_lines_in_sample() -> Result<()> {
+        let players = ListingPlayers::default()
+            .info_line(&Text::from("Welcome!").color(NamedTextColor::Gold))
+            .info_line(&"Have fun".into());
+        let expected = json!({
+            "online": 0,
+            "max": 1,
+            "sample": [
+                { "name": "\u{00a7}6Welcome!", "id": "00000000-0000-0000-0000-000000000000" },
+                { "name": "Have fun", "id": "00000000-0000-0000-0000-000000000000" },
+            ],
+        });
+        assert_eq!(expected, serde_json::to_value(players)?);
+        Ok(())
+    }
+
+    #[test]
+    fn packet_ids_in_const_context() {
+        const STATUS_IDS: [i32; 2] = [StatusRequest::ID, PingRequest::ID];
+        assert_eq!([0x00, 0x01], STATUS_IDS);
+    }
+
+    #[test]
+    fn status_template_matches_listing() -> Result<()> {
+        let mut listing = Listing::minimal("Hello".into());
+        let template = StatusTemplate::new(&listing)?;
+
+        listing.players.current = 42;
+        assert_eq!(StatusResponse(listing).to_json()?, template.render(42));
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{bail, Context, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ProxyHeader {
+    // Health checks and unknown protocols don't carry a source address
+    pub source: Option<SocketAddr>,
+}
+
+// Returns the header and its length, or None if more bytes are needed
+pub fn parse_header(bytes: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+    if starts_with_partial(bytes, V1_PREFIX) {
+        parse_v1(bytes)
+    } else if starts_with_partial(bytes, V2_SIGNATURE) {
+        parse_v2(bytes)
+    } else {
+        bail!("the connection didn't start with a PROXY protocol header");
+    }
+}
+
+fn starts_with_partial(bytes: &[u8], prefix: &[u8]) -> bool {
+    let len = bytes.len().min(prefix.len());
+    bytes[..len] == prefix[..len]
+}
+
+fn parse_v1(bytes: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+    let Some(end) = bytes.windows(2).position(|window| window == b"\r\n") else {
+        if bytes.len() >= V1_MAX_LEN {
+            bail!("the PROXY protocol v1 header is longer than {V1_MAX_LEN} bytes");
+        }
+        return Ok(None);
+    };
+    let line = str::from_utf8(&bytes[..end]).context("the v1 header isn't valid UTF-8")?;
+
+    let parts = line.split(' ').collect::<Vec<_>>();
+    let source = match parts[..] {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .context("failed to parse the source address")?;
+            let port = port.parse().context("failed to parse the source port")?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => bail!("the v1 header {line:?} is malformed"),
+    };
+    Ok(Some((ProxyHeader { source }, end + 2)))
+}
+
+fn parse_v2(bytes: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+    let Some(header) = bytes.get(..16) else {
+        return Ok(None);
+    };
+    let version_command = header[12];
+    if version_command >> 4 != 2 {
+        bail!("the v2 header has version {}", version_command >> 4);
+    }
+    let len = 16 + usize::from(u16::from_be_bytes([header[14], header[15]]));
+    let Some(addrs) = bytes.get(16..len) else {
+        return Ok(None);
+    };
+
+    // LOCAL connections come from the proxy itself
+    let source = if version_command & 0x0f == 0 {
+        None
+    } else {
+        match header[13] >> 4 {
+            1 if addrs.len() >= 12 => {
+                let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[..4])?);
+                Some(SocketAddr::new(
+                    ip.into(),
+                    u16::from_be_bytes([addrs[8], addrs[9]]),
+                ))
+            }
+            2 if addrs.len() >= 36 => {
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[..16])?);
+                Some(SocketAddr::new(
+                    ip.into(),
+                    u16::from_be_bytes([addrs[32], addrs[33]]),
+                ))
+            }
+            1 | 2 => bail!("the v2 header's addresses are truncated"),
+            _ => None,
+        }
+    };
+    Ok(Some((ProxyHeader { source }, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_v2_tcp4() -> Result<()> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 12]);
+        header.extend([203, 0, 113, 7, 10, 0, 0, 1]);
+        header.extend([0xd4, 0x31, 0x63, 0xdd]);
+        header.push(0x10);
+
+        let (parsed, len) = parse_header(&header)?.context("the header is incomplete")?;
+        assert_eq!(28, len);
+        assert_eq!(Some("203.0.113.7:54321".parse()?), parsed.source);
+        assert_eq!(None, parse_header(&header[..20])?);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_header_is_an_error() {
+        assert!(parse_header(b"\x10\x00\xf9\x05").is_err());
+        assert!(parse_header(b"PROXY TCP4 nonsense\r\n").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+use crate::mc::net::{Connection, ConnectionIo, ConnectionState};
+use anyhow::{Context, Result};
+use flate2::read::ZlibDecoder;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex, Once};
+use std::time::Duration;
+
+pub struct DuplexStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+#[derive(Default)]
+struct Pipe {
+    bytes: Mutex<PipeBytes>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct PipeBytes {
+    buf: VecDeque<u8>,
+    closed: bool,
+}
+
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let first = Arc::new(Pipe::default());
+    let second = Arc::new(Pipe::default());
+    let a = DuplexStream {
+        incoming: Arc::clone(&first),
+        outgoing: Arc::clone(&second),
+        read_timeout: Cell::new(None),
+    };
+    let b = DuplexStream {
+        incoming: second,
+        outgoing: first,
+        read_timeout: Cell::new(None),
+    };
+    (a, b)
+}
+
+pub fn connection() -> (Connection, DuplexStream) {
+    let (server_side, client) = duplex();
+    (Connection::new(server_side), client)
+}
+
+pub fn play_connection() -> (Connection, DuplexStream) {
+    let (mut connection, client) = connection();
+    connection.set_state(ConnectionState::Login).unwrap();
+    connection.set_state(ConnectionState::Play).unwrap();
+    (connection, client)
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut bytes = self.incoming.bytes.lock().unwrap();
+        while bytes.buf.is_empty() && !bytes.closed {
+            bytes = match self.read_timeout.get() {
+                Some(timeout) => {
+                    let (bytes, result) = self
+                        .incoming
+                        .available
+                        .wait_timeout(bytes, timeout)
+                        .unwrap();
+                    if result.timed_out() && bytes.buf.is_empty() {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                    bytes
+                }
+                None => self.incoming.available.wait(bytes).unwrap(),
+            };
+        }
+
+        let len = buf.len().min(bytes.buf.len());
+        for (dest, src) in buf.iter_mut().zip(bytes.buf.drain(..len)) {
+            *dest = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = self.outgoing.bytes.lock().unwrap();
+        bytes.buf.extend(buf);
+        self.outgoing.available.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ConnectionIo for DuplexStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        for pipe in [&self.incoming, &self.outgoing] {
+            pipe.bytes.lock().unwrap().closed = true;
+            pipe.available.notify_all();
+        }
+    }
+}
+
+pub fn write_packet(stream: &mut impl Write, id: i32, body: &[u8], compressed: bool) -> Result<()> {
+    let mut data = vec![];
+    if compressed {
+        data.write_var(0)?;
+    }
+    data.write_var(id)?;
+    data.extend_from_slice(body);
+
+    stream.write_var::<i32>(data.len().try_into()?)?;
+    stream.write_all(&data)?;
+    Ok(())
+}
+
+pub fn read_packet(stream: &mut impl Read, compressed: bool) -> Result<(i32, Vec<u8>)> {
+    let len = stream
+        .read_var::<i32>()
+        .context("failed to read the packet length")?;
+    let mut frame = vec![0; len.try_into()?];
+    stream
+        .read_exact(&mut frame)
+        .context("failed to read the packet body")?;
+
+    let mut slice = &frame[..];
+    let data = if compressed {
+        let data_len = slice.read_var::<i32>()?;
+        if data_len != 0 {
+            let mut data = vec![0; data_len.try_into()?];
+            ZlibDecoder::new(slice).read_exact(&mut data)?;
+            data
+        } else {
+            slice.to_vec()
+        }
+    } else {
+        frame
+    };
+
+    let mut slice = &data[..];
+    let id = slice.read_var()?;
+    Ok((id, slice.to_vec()))
+}
+
+// Records everything written to it and reads as a closed stream
+#[derive(Clone, Default)]
+pub struct CaptureStream {
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for CaptureStream {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for CaptureStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ConnectionIo for CaptureStream {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs send against a Play connection and returns every packet it sent, in order
+pub fn capture_packets<F>(send: F) -> Result<Vec<(i32, Vec<u8>)>>
+where
+    F: FnOnce(&mut Connection) -> Result<()>,
+{
+    let stream = CaptureStream::default();
+    let mut connection = Connection::new(stream.clone());
+    connection.set_state(ConnectionState::Login)?;
+    connection.set_state(ConnectionState::Play)?;
+    send(&mut connection)?;
+    connection
+        .finish_writes()
+        .context("failed to write the captured packets")?;
+
+    let written = stream.written.lock().unwrap();
+    let mut slice = &written[..];
+    let mut packets = vec![];
+    while !slice.is_empty() {
+        let packet = read_packet(&mut slice, connection.compressed)
+            .with_context(|| format!("failed to read captured packet {}", packets.len()))?;
+        packets.push(packet);
+    }
+    Ok(packets)
+}
+
+// The logger is process-wide, so every test shares this one and filters by message
+static LOGS: CapturingLogger = CapturingLogger(Mutex::new(vec![]));
+
+struct CapturingLogger(Mutex<Vec<(String, String)>>);
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let entry = (record.target().to_string(), record.args().to_string());
+        self.0.lock().unwrap().push(entry);
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn capture_logs() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&LOGS).expect("another logger is already installed");
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+pub fn logged_target(message: &str) -> Option<String> {
+    let logs = LOGS.0.lock().unwrap();
+    logs.iter()
+        .find(|(_, logged)| logged == message)
+        .map(|(target, _)| target.clone())
+}
@@ -1,39 +1,53 @@
 use crate::mc::net::login::{LoginSuccess, SetCompression};
-use crate::mc::net::play::setup;
-use crate::mc::net::{Connection, ConnectionState};
-use crate::server::Server;
+use crate::mc::net::play::{entity, setup};
+use crate::mc::net::{Connection, ConnectionState, EncodablePacket, PacketFromServer};
+use crate::mc::text::Text;
+use crate::server::{ConnectionAction, Server};
 use anyhow::{Context, Result};
 use log::info;
 use num_enum::IntoPrimitive;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
 use uuid::Uuid;
 
+const LOG_TARGET: &str = "minestodon::player";
+
 pub struct Player {
     pub connection: Connection,
     pub server: Server,
     uuid: Uuid,
+    commands: Receiver<OutgoingCommand>,
+    command_sender: Sender<OutgoingCommand>,
 
     pub username: String,
 }
 
 impl Player {
     pub fn new(connection: Connection, username: String, server: Server) -> Self {
-        let uuid = Uuid::new_v4();
-        info!("Assigning UUID {uuid} to player {}.", username);
+        let uuid = server.config().uuid_strategy.assign(&username);
+        info!(target: LOG_TARGET, "Assigning UUID {uuid} to player {}.", username);
 
+        let (command_sender, commands) = mpsc::channel();
         Self {
             connection,
             server,
             uuid,
+            commands,
+            command_sender,
             username,
         }
     }
 
     pub fn finish_joining(&mut self) -> Result<()> {
-        let compression = SetCompression(Connection::COMPRESSION_THRESHOLD);
-        self.connection
-            .send_packet(compression)
-            .context("failed to send the desired compression threshold")?;
-        self.connection.compressed = true;
+        let threshold = self.connection.compression_threshold();
+        if threshold >= 0 {
+            self.connection
+                .send_packet(SetCompression(threshold))
+                .context("failed to send the desired compression threshold")?;
+            self.connection.compressed = true;
+        }
 
         let success = LoginSuccess {
             uuid: self.uuid,
@@ -44,18 +58,103 @@ impl Player {
             .send_packet(success)
             .context("failed to send the login success packet")?;
 
-        self.connection.set_state(ConnectionState::Play);
+        self.connection
+            .set_state(ConnectionState::Play)
+            .context("failed to switch to the play state")?;
         setup::set_up(&mut self.connection, &self.server)
             .context("failed to set up after login")?;
         Ok(())
     }
 
-    pub fn tick(&mut self, _server: &Server) -> Result<()> {
-        Ok(())
+    pub fn tick(&mut self, _server: &Server) -> Result<ConnectionAction> {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                OutgoingCommand::Packet(packet) => self
+                    .connection
+                    .send_dyn_packet(&*packet)
+                    .context("failed to send a queued packet")?,
+                OutgoingCommand::Kick(reason) => {
+                    self.connection
+                        .send_kick(reason)
+                        .context("failed to kick the player")?;
+                    return Ok(ConnectionAction::Close);
+                }
+            }
+        }
+        Ok(ConnectionAction::DoNothing)
+    }
+
+    pub fn spawn_floating_text(&mut self, pos: (f64, f64, f64), text: Text) -> Result<i32> {
+        let entity_id = self
+            .server
+            .next_entity_id()
+            .context("failed to assign the armor stand's entity ID")?;
+        let (spawn, metadata) = entity::floating_text(entity_id, pos, text);
+        self.connection
+            .bundle(|connection| {
+                connection
+                    .send_packet(spawn)
+                    .context("failed to spawn the armor stand")?;
+                connection
+                    .send_packet(metadata)
+                    .context("failed to send the armor stand metadata")
+            })
+            .context("failed to send the floating text")?;
+        Ok(entity_id)
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    pub fn handle(&self) -> PlayerHandle {
+        PlayerHandle {
+            uuid: self.uuid,
+            username: self.username.clone(),
+            commands: self.command_sender.clone(),
+        }
+    }
+}
+
+// Connections live on their own threads, so other threads reach them through this channel,
+// which is drained whenever the player ticks
+#[derive(Clone, Debug)]
+pub struct PlayerHandle {
+    pub uuid: Uuid,
+    pub username: String,
+    commands: Sender<OutgoingCommand>,
+}
+
+impl PlayerHandle {
+    pub fn send<P: PacketFromServer + Send + 'static>(&self, packet: P) -> bool {
+        self.commands
+            .send(OutgoingCommand::Packet(Box::new(packet)))
+            .is_ok()
+    }
+
+    pub fn kick(&self, reason: Text) -> bool {
+        self.commands.send(OutgoingCommand::Kick(reason)).is_ok()
     }
 }
 
-#[derive(Copy, Clone, IntoPrimitive)]
+pub enum OutgoingCommand {
+    Packet(Box<dyn EncodablePacket>),
+    Kick(Text),
+}
+
+impl Debug for OutgoingCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Packet(packet) => f
+                .debug_struct("Packet")
+                .field("id", &packet.id())
+                .finish_non_exhaustive(),
+            Self::Kick(reason) => f.debug_tuple("Kick").field(reason).finish(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, IntoPrimitive)]
 #[repr(i8)]
 pub enum GameMode {
     Survival,
@@ -63,3 +162,75 @@ pub enum GameMode {
     Adventure,
     Spectator,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::play::container::SetContainerProperty;
+    use crate::mc::net::test_util;
+    use crate::mc::registry;
+    use std::{iter, thread};
+
+    #[test]
+    fn compression_enabled_after_joining() -> Result<()> {
+        registry::init();
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, _client) = test_util::connection();
+        connection.set_state(ConnectionState::Login)?;
+        assert!(!connection.compression_enabled());
+
+        let mut player = Player::new(connection, "joiner".into(), server);
+        player.finish_joining()?;
+        assert!(player.connection.compression_enabled());
+        assert_eq!(
+            Connection::COMPRESSION_THRESHOLD,
+            player.connection.compression_threshold()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn negative_threshold_disables_compression() -> Result<()> {
+        registry::init();
+        let server = Server::bind("127.0.0.1:0")?;
+        let (mut connection, mut client) = test_util::connection();
+        connection.set_state(ConnectionState::Login)?;
+        connection.set_compression_threshold(-1);
+
+        let mut player = Player::new(connection, "joiner".into(), server);
+        player.finish_joining()?;
+        assert!(!player.connection.compression_enabled());
+        player.connection.send_raw(0x7f, &[0; 4096])?;
+        player.connection.finish_writes()?;
+        drop(player);
+
+        let (id, _) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(LoginSuccess::ID, id);
+        let large = iter::from_fn(|| test_util::read_packet(&mut client, false).ok())
+            .find(|(id, _)| *id == 0x7f)
+            .context("the large packet wasn't sent uncompressed")?;
+        assert_eq!(vec![0; 4096], large.1);
+        Ok(())
+    }
+
+    #[test]
+    fn packets_from_other_threads_are_sent_on_tick() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (connection, mut client) = test_util::play_connection();
+        let mut player = Player::new(connection, "remote".into(), Server::clone(&server));
+
+        let handle = player.handle();
+        let property = SetContainerProperty {
+            window_id: 1,
+            property: 2,
+            value: 3,
+        };
+        thread::spawn(move || handle.send(property)).join().unwrap();
+        assert!(matches!(player.tick(&server)?, ConnectionAction::DoNothing));
+
+        let (id, body) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(SetContainerProperty::ID, id);
+        assert_eq!(vec![1, 0, 2, 0, 3], body);
+        Ok(())
+    }
+}
@@ -1,10 +1,16 @@
 use crate::mc::world::{Biome, DimensionType};
 use crate::mc::{world, Identifier};
+use anyhow::{bail, Result};
+use log::debug;
 use minestodon_macros::minecraft;
+use serde::ser;
 use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Once, RwLock};
+
+const LOG_TARGET: &str = "minestodon::registry";
 
 pub static BIOMES: Registry<Biome> =
     Registry::new(minecraft!("worldgen/biome"), world::register_biomes);
@@ -18,24 +24,39 @@ pub static DIMENSION_TYPES: Registry<DimensionType> = Registry::new(
 pub static MESSAGE_TYPES: Registry<()> = Registry::new(minecraft!("chat_type"), |_| {});
 
 pub fn init() {
-    BIOMES.init();
-    DIMENSION_TYPES.init();
-    MESSAGE_TYPES.init();
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        BIOMES.init();
+        DIMENSION_TYPES.init();
+        MESSAGE_TYPES.init();
+    });
 }
 
+type InitFn<T> = fn(&Registry<T>);
+
 pub struct Registry<T> {
     pub id: Identifier,
-    entries: RwLock<Option<HashMap<Identifier, T>>>,
-    init_fn: fn(&Registry<T>),
+    entries: RwLock<Option<Vec<(Identifier, T)>>>,
+    init_fn: InitFn<T>,
+    extra_init_fns: RwLock<Vec<InitFn<T>>>,
 }
 
 impl<T> Registry<T> {
-    pub const fn new(id: Identifier, init: fn(&Registry<T>)) -> Self {
+    pub const fn new(id: Identifier, init: InitFn<T>) -> Self {
         Self {
             id,
             entries: RwLock::new(None),
             init_fn: init,
+            extra_init_fns: RwLock::new(Vec::new()),
+        }
+    }
+
+    // Lets embedders add their own entries, which are registered after the built-in ones
+    pub fn add_init_fn(&self, init: InitFn<T>) {
+        if self.entries.read().unwrap().is_some() {
+            panic!("the registry has already been initialized");
         }
+        self.extra_init_fns.write().unwrap().push(init);
     }
 
     pub fn init(&self) {
@@ -44,23 +65,48 @@ impl<T> Registry<T> {
             if entries.is_some() {
                 panic!("the registry has already been initialized");
             }
-            *entries = Some(HashMap::new());
+            *entries = Some(Vec::new());
         }
         (self.init_fn)(self);
+        let extra_init_fns = self.extra_init_fns.read().unwrap().clone();
+        for init in extra_init_fns {
+            init(self);
+        }
+
+        let len = self.read_entries(|entries| entries.len());
+        debug!(target: LOG_TARGET, "Initialized the {} registry with {len} entries.", self.id);
     }
 
-    pub fn register(&self, key: Identifier, value: T) {
+    pub fn register(&self, key: Identifier, value: T) -> Result<()> {
         self.write_entries(|entries| {
-            if entries.contains_key(&key) {
-                panic!("the registry already contains {key}");
+            if entries.iter().any(|(entry, _)| *entry == key) {
+                bail!("the {} registry already contains {key}", self.id);
+            }
+            entries.push((key, value));
+            Ok(())
+        })
+    }
+
+    pub fn id_of(&self, key: &Identifier) -> Option<i32> {
+        self.read_entries(|entries| {
+            entries
+                .iter()
+                .position(|(entry, _)| entry == key)
+                .and_then(|id| id.try_into().ok())
+        })
+    }
+
+    pub fn for_each(&self, mut f: impl FnMut(&Identifier, &T)) {
+        self.read_entries(|entries| {
+            for (key, value) in entries {
+                f(key, value);
             }
-            entries.insert(key, value);
         });
     }
 
     fn read_entries<F, R>(&self, f: F) -> R
     where
-        F: FnOnce(&HashMap<Identifier, T>) -> R,
+        F: FnOnce(&[(Identifier, T)]) -> R,
     {
         let locked = self.entries.read().unwrap();
         let entries = locked
@@ -71,7 +117,7 @@ impl<T> Registry<T> {
 
     fn write_entries<F, R>(&self, f: F) -> R
     where
-        F: FnOnce(&mut HashMap<Identifier, T>) -> R,
+        F: FnOnce(&mut Vec<(Identifier, T)>) -> R,
     {
         let mut locked = self.entries.write().unwrap();
         let entries = locked
@@ -81,6 +127,19 @@ impl<T> Registry<T> {
     }
 }
 
+impl<T> Debug for Registry<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let entries = self.entries.read().unwrap();
+        f.debug_struct("Registry")
+            .field("id", &self.id)
+            .field("entries", &*entries)
+            .finish()
+    }
+}
+
 #[derive(Serialize)]
 struct SerializableRegistryEntry<'a, T>
 where
@@ -91,6 +150,12 @@ where
     pub element: &'a T,
 }
 
+fn entry_id<E: ser::Error>(index: usize) -> Result<i32, E> {
+    index
+        .try_into()
+        .map_err(|_| E::custom(format!("registry entry {index} doesn't have an i32 ID")))
+}
+
 impl<T> Serialize for Registry<T>
 where
     T: Serialize,
@@ -103,12 +168,11 @@ where
             let entries = entries
                 .iter()
                 .enumerate()
-                .filter_map(|(id, (name, element))| {
-                    id.try_into()
-                        .ok()
-                        .map(|id| SerializableRegistryEntry { name, id, element })
+                .map(|(index, (name, element))| {
+                    let id = entry_id(index)?;
+                    Ok(SerializableRegistryEntry { name, id, element })
                 })
-                .collect::<Vec<_>>();
+                .collect::<Result<Vec<_>, S::Error>>()?;
 
             let mut map = serializer.serialize_map(Some(2))?;
             map.serialize_entry("type", &self.id)?;
@@ -117,3 +181,79 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::world::{BiomeEffects, BiomePrecipitation, BiomeWeather};
+    use minestodon_macros::minestodon;
+
+    #[test]
+    fn for_each_in_registration_order() {
+        let registry = Registry::new(minecraft!("worldgen/biome"), world::register_biomes);
+        registry.init();
+
+        let mut keys = vec![];
+        registry.for_each(|key, _| keys.push(key.clone()));
+        assert_eq!(vec![world::BIOME, minecraft!("plains")], keys);
+        assert_eq!(Some(1), registry.id_of(&minecraft!("plains")));
+    }
+
+    #[test]
+    fn embedder_biomes_are_serialized() -> serde_json::Result<()> {
+        let registry = Registry::new(minecraft!("worldgen/biome"), world::register_biomes);
+        registry.add_init_fn(|registry| {
+            let biome = Biome {
+                weather: BiomeWeather {
+                    precipitation: BiomePrecipitation::None,
+                    temperature: 2.0,
+                    temperature_modifier: None,
+                    downfall: 0.0,
+                },
+                effects: BiomeEffects {
+                    fog_color: 0xc0d8ff,
+                    water_color: 0x3f76e4,
+                    water_fog_color: 0x050533,
+                    sky_color: 0x6eb1ff,
+                    foliage_color: None,
+                    grass_color: None,
+                    grass_color_modifier: None,
+                },
+            };
+            world::register_biome(registry, minestodon!("custom"), biome).unwrap();
+        });
+        registry.init();
+
+        let json = serde_json::to_value(&registry)?;
+        let entry = &json["value"][2];
+        assert_eq!("minestodon:custom", entry["name"]);
+        assert_eq!(2, entry["id"]);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_registration_is_an_error() {
+        let registry = Registry::new(minestodon!("test_duplicates"), |_| {});
+        registry.init();
+        registry.register(minestodon!("entry"), ()).unwrap();
+        let err = registry.register(minestodon!("entry"), ()).unwrap_err();
+        assert_eq!(
+            "the minestodon:test_duplicates registry already contains minestodon:entry",
+            err.to_string()
+        );
+        registry.for_each(|key, _| assert_eq!(minestodon!("entry"), *key));
+    }
+
+    #[test]
+    fn entry_id_overflow_is_an_error() {
+        assert_eq!(
+            i32::MAX,
+            entry_id::<serde_json::Error>(i32::MAX as usize).unwrap()
+        );
+        let err = entry_id::<serde_json::Error>(i32::MAX as usize + 1).unwrap_err();
+        assert_eq!(
+            "registry entry 2147483648 doesn't have an i32 ID",
+            err.to_string()
+        );
+    }
+}
@@ -1,12 +1,14 @@
-use anyhow::{Context, Result};
+use crate::mc::Identifier;
+use anyhow::{bail, Context, Result};
 use enum_iterator::Sequence;
 use lab::Lab;
 use serde::{Deserialize, Serialize};
 use serde_json::Number;
 use std::fmt;
 use std::fmt::{Display, Formatter, Write};
+use uuid::Uuid;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Text {
     String(String),
@@ -17,6 +19,8 @@ pub enum Text {
 }
 
 impl Text {
+    pub const MAX_JSON_LEN: usize = 262144;
+
     pub fn push_child(self, child: impl Into<Text>) -> Self {
         self.modify_as_full(|full| full.children.push(child.into()))
     }
@@ -58,6 +62,122 @@ impl Text {
         self.modify_as_full(|full| full.formatting.obfuscated = Some(obfuscated))
     }
 
+    pub fn click_open_url(self, url: impl Into<String>) -> Self {
+        self.modify_as_full(|full| full.click_event = Some(ClickEvent::OpenUrl(url.into())))
+    }
+
+    pub fn click_run_command(self, command: impl Into<String>) -> Self {
+        let event = ClickEvent::RunCommand(command.into());
+        self.modify_as_full(|full| full.click_event = Some(event))
+    }
+
+    pub fn click_suggest_command(self, command: impl Into<String>) -> Self {
+        let event = ClickEvent::SuggestCommand(command.into());
+        self.modify_as_full(|full| full.click_event = Some(event))
+    }
+
+    pub fn click_copy_to_clipboard(self, text: impl Into<String>) -> Self {
+        let event = ClickEvent::CopyToClipboard(text.into());
+        self.modify_as_full(|full| full.click_event = Some(event))
+    }
+
+    pub fn hover_show_text(self, text: impl Into<Text>) -> Self {
+        let event = HoverEvent::ShowText(Box::new(text.into()));
+        self.modify_as_full(|full| full.hover_event = Some(event))
+    }
+
+    pub fn hover_show_item(self, id: Identifier, count: i32, tag: Option<String>) -> Self {
+        let event = HoverEvent::ShowItem(Box::new(HoveredItem { id, count, tag }));
+        self.modify_as_full(|full| full.hover_event = Some(event))
+    }
+
+    pub fn hover_show_entity(self, kind: Identifier, id: Uuid, name: Option<Text>) -> Self {
+        let event = HoverEvent::ShowEntity(Box::new(HoveredEntity { kind, id, name }));
+        self.modify_as_full(|full| full.hover_event = Some(event))
+    }
+
+    // Children inherit the first element of a sequential, so that one is never merged
+    pub fn flatten(self) -> Self {
+        match self {
+            Self::Sequential(mut texts) if !texts.is_empty() => {
+                let parent = texts.remove(0).flatten();
+                let mut children = merge_segments(texts);
+                if parent.to_plain_segment() == Some(Default::default()) && children.len() <= 1 {
+                    return children.pop().unwrap_or(parent);
+                }
+
+                children.insert(0, parent);
+                Self::Sequential(children)
+            }
+            Self::Full(mut full) => {
+                full.children = merge_segments(full.children);
+                Self::Full(full)
+            }
+            text => text,
+        }
+    }
+
+    fn to_plain_segment(&self) -> Option<(String, TextFormatting, Option<ClickEvent>)> {
+        match self {
+            Self::String(_) | Self::Bool(_) | Self::Number(_) => {
+                Some((self.to_plain_string(), TextFormatting::default(), None))
+            }
+            Self::Full(FullText {
+                content: TextContent::Plain { text },
+                children,
+                formatting,
+                click_event,
+                hover_event: None,
+            }) if children.is_empty() => {
+                Some((text.clone(), formatting.clone(), click_event.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_ansi(input: &str) -> Self {
+        let mut segments = vec![Text::String(String::new())];
+        let mut formatting = TextFormatting::default();
+        let mut rest = input;
+        while let Some(start) = rest.find("\x1b[") {
+            push_formatted_segment(&mut segments, &rest[..start], &formatting);
+            let escape = &rest[start + 2..];
+            let Some(end) = escape.find(|c: char| !c.is_ascii_digit() && c != ';') else {
+                rest = "";
+                break;
+            };
+            if escape[end..].starts_with('m') {
+                formatting.apply_sgr(&escape[..end]);
+            }
+            let final_len = escape[end..].chars().next().map_or(0, char::len_utf8);
+            rest = &escape[end + final_len..];
+        }
+        push_formatted_segment(&mut segments, rest, &formatting);
+        Self::Sequential(segments).flatten()
+    }
+
+    // Like the client, color codes also reset the formatting codes before them
+    pub fn from_legacy_string(input: &str) -> Self {
+        let mut segments = vec![Text::String(String::new())];
+        let mut formatting = TextFormatting::default();
+        let mut parts = input.split(LEGACY_ESCAPE_CHAR);
+        if let Some(first) = parts.next() {
+            push_formatted_segment(&mut segments, first, &formatting);
+        }
+        for part in parts {
+            let mut chars = part.chars();
+            if let Some(code) = chars.next() {
+                formatting.apply_legacy_code(code);
+            }
+            push_formatted_segment(&mut segments, chars.as_str(), &formatting);
+        }
+        Self::Sequential(segments).flatten()
+    }
+
+    pub fn into_full(self) -> Self {
+        self.modify_as_full(|_| ())
+    }
+
     fn modify_as_full(self, modify: impl FnOnce(&mut FullText)) -> Self {
         let mut full = match self {
             Text::Full(full) => full,
@@ -68,6 +188,8 @@ impl Text {
                 content: TextContent::default(),
                 children,
                 formatting: TextFormatting::default(),
+                click_event: None,
+                hover_event: None,
             },
         };
         modify(&mut full);
@@ -117,6 +239,81 @@ impl Text {
         }
     }
 
+    pub fn to_ansi_string(&self) -> String {
+        let mut ansi = String::new();
+        let mut current = TextFormatting::default();
+        self.write_ansi(&mut ansi, &TextFormatting::default(), &mut current);
+        ansi.push_str("\x1b[0m");
+        ansi
+    }
+
+    fn write_ansi(&self, ansi: &mut String, parent: &TextFormatting, current: &mut TextFormatting) {
+        let mut write_segment = |text: &str, formatting: &TextFormatting| {
+            if text.is_empty() {
+                return;
+            }
+            if formatting != current {
+                if *current != TextFormatting::default() {
+                    ansi.push_str("\x1b[0m");
+                }
+                ansi.push_str(&formatting.ansi_codes());
+                *current = formatting.clone();
+            }
+            ansi.push_str(text);
+        };
+        match self {
+            Self::Sequential(texts) => {
+                // Like in flatten, everything after the first element inherits from it
+                let Some((first, rest)) = texts.split_first() else {
+                    return;
+                };
+                first.write_ansi(ansi, parent, current);
+                let formatting = first.own_formatting().inherit(parent);
+                for text in rest {
+                    text.write_ansi(ansi, &formatting, current);
+                }
+            }
+            Self::Full(full) => {
+                let formatting = full.formatting.inherit(parent);
+                write_segment(&full.content.to_string(), &formatting);
+                for child in &full.children {
+                    child.write_ansi(ansi, &formatting, current);
+                }
+            }
+            _ => write_segment(&self.to_plain_string(), parent),
+        }
+    }
+
+    fn own_formatting(&self) -> TextFormatting {
+        match self {
+            Self::Full(full) => full.formatting.clone(),
+            _ => TextFormatting::default(),
+        }
+    }
+
+    pub fn plain_len(&self) -> usize {
+        self.to_plain_string().chars().count()
+    }
+
+    pub fn json_len(&self) -> Result<usize> {
+        let json = self
+            .to_json_string(JsonStringType::Short)
+            .context("failed to serialize the text")?;
+        Ok(json.chars().count())
+    }
+
+    // Clients throw an error instead of showing chat that's larger than this
+    pub fn check_json_len(&self) -> Result<()> {
+        let len = self.json_len()?;
+        if len > Self::MAX_JSON_LEN {
+            bail!(
+                "the text is {len} characters long as JSON, but the limit is {}",
+                Self::MAX_JSON_LEN
+            );
+        }
+        Ok(())
+    }
+
     pub fn to_json_string(&self, str_type: JsonStringType) -> Result<String> {
         let string = match str_type {
             JsonStringType::Short => serde_json::to_string(self)?,
@@ -132,7 +329,51 @@ impl<D: Display> From<D> for Text {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+fn push_formatted_segment(segments: &mut Vec<Text>, text: &str, formatting: &TextFormatting) {
+    if text.is_empty() {
+        return;
+    }
+    let segment = if *formatting == TextFormatting::default() {
+        Text::String(text.to_string())
+    } else {
+        Text::Full(FullText {
+            formatting: formatting.clone(),
+            ..text.into()
+        })
+    };
+    segments.push(segment);
+}
+
+fn merge_segments(texts: Vec<Text>) -> Vec<Text> {
+    let mut merged: Vec<Text> = Vec::with_capacity(texts.len());
+    for text in texts.into_iter().map(Text::flatten) {
+        let previous = merged.last().and_then(Text::to_plain_segment);
+        match (previous, text.to_plain_segment()) {
+            (
+                Some((mut joined, formatting, click_event)),
+                Some((next, next_formatting, next_click)),
+            ) if formatting == next_formatting && click_event == next_click => {
+                joined.push_str(&next);
+                let segment = if formatting == TextFormatting::default() && click_event.is_none() {
+                    Text::String(joined)
+                } else {
+                    Text::Full(FullText {
+                        content: TextContent::Plain { text: joined },
+                        children: vec![],
+                        formatting,
+                        click_event,
+                        hover_event: None,
+                    })
+                };
+                *merged.last_mut().unwrap() = segment;
+            }
+            _ => merged.push(text),
+        }
+    }
+    merged
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FullText {
     #[serde(flatten)]
     content: TextContent,
@@ -140,7 +381,18 @@ pub struct FullText {
     children: Vec<Text>,
     #[serde(flatten)]
     formatting: TextFormatting,
-    // TODO: interactivity
+    #[serde(
+        rename = "clickEvent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    click_event: Option<ClickEvent>,
+    #[serde(
+        rename = "hoverEvent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    hover_event: Option<HoverEvent>,
 }
 
 impl<D: Display> From<D> for FullText {
@@ -151,11 +403,13 @@ impl<D: Display> From<D> for FullText {
             },
             children: vec![],
             formatting: TextFormatting::default(),
+            click_event: None,
+            hover_event: None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum TextContent {
     Plain {
@@ -191,7 +445,54 @@ impl Display for TextContent {
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "action", content = "value", rename_all = "snake_case")]
+pub enum ClickEvent {
+    OpenUrl(String),
+    RunCommand(String),
+    SuggestCommand(String),
+    CopyToClipboard(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "action", content = "contents", rename_all = "snake_case")]
+pub enum HoverEvent {
+    ShowText(Box<Text>),
+    ShowItem(Box<HoveredItem>),
+    ShowEntity(Box<HoveredEntity>),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HoveredItem {
+    pub id: Identifier,
+    // Vanilla leaves out the count when there's only one item
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub count: i32,
+    // The item's NBT in its SNBT string form
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+fn one() -> i32 {
+    1
+}
+
+fn is_one(count: &i32) -> bool {
+    *count == 1
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HoveredEntity {
+    #[serde(rename = "type")]
+    pub kind: Identifier,
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Text>,
+}
+
+const LEGACY_ESCAPE_CHAR: char = '\u{00a7}';
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct TextFormatting {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub color: Option<TextColor>,
@@ -219,35 +520,129 @@ pub struct TextFormatting {
 
 impl TextFormatting {
     pub fn legacy_codes(&self) -> String {
-        const ESCAPE_CHAR: char = '\u{00a7}';
-
         let mut codes = String::with_capacity(2 * 6);
         if let Some(color) = &self.color {
             let legacy = color
                 .legacy_char()
-                .unwrap_or_else(|_| NamedTextColor::Reset.legacy_char());
-            write!(codes, "{ESCAPE_CHAR}{legacy}").unwrap();
+                .unwrap_or_else(|_| NamedTextColor::White.legacy_char());
+            write!(codes, "{LEGACY_ESCAPE_CHAR}{legacy}").unwrap();
         }
         if let Some(true) = self.bolded {
-            write!(codes, "{ESCAPE_CHAR}l").unwrap();
+            write!(codes, "{LEGACY_ESCAPE_CHAR}l").unwrap();
         }
         if let Some(true) = self.italicized {
-            write!(codes, "{ESCAPE_CHAR}o").unwrap();
+            write!(codes, "{LEGACY_ESCAPE_CHAR}o").unwrap();
         }
         if let Some(true) = self.underlined {
-            write!(codes, "{ESCAPE_CHAR}n").unwrap();
+            write!(codes, "{LEGACY_ESCAPE_CHAR}n").unwrap();
         }
         if let Some(true) = self.struck_through {
-            write!(codes, "{ESCAPE_CHAR}m").unwrap();
+            write!(codes, "{LEGACY_ESCAPE_CHAR}m").unwrap();
         }
         if let Some(true) = self.obfuscated {
-            write!(codes, "{ESCAPE_CHAR}k").unwrap();
+            write!(codes, "{LEGACY_ESCAPE_CHAR}k").unwrap();
         }
         codes
     }
+
+    // Empty if there's nothing to change from the terminal's default style
+    fn ansi_codes(&self) -> String {
+        let mut codes = vec![];
+        let flags = [
+            (self.bolded, "1"),
+            (self.italicized, "3"),
+            (self.underlined, "4"),
+            (self.struck_through, "9"),
+        ];
+        for (flag, code) in flags {
+            if let Some(true) = flag {
+                codes.push(code.to_string());
+            }
+        }
+        let rgb = match &self.color {
+            Some(TextColor::Named(NamedTextColor::Reset)) | None => None,
+            Some(TextColor::Named(named)) => Some(named.vanilla()),
+            Some(TextColor::Hex(hex)) => parse_hex(hex).ok(),
+        };
+        if let Some([red, green, blue]) = rgb {
+            codes.push(format!("38;2;{red};{green};{blue}"));
+        }
+
+        if codes.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", codes.join(";"))
+        }
+    }
+
+    fn inherit(&self, parent: &Self) -> Self {
+        Self {
+            color: self.color.clone().or_else(|| parent.color.clone()),
+            font: self.font.or(parent.font),
+            bolded: self.bolded.or(parent.bolded),
+            italicized: self.italicized.or(parent.italicized),
+            underlined: self.underlined.or(parent.underlined),
+            struck_through: self.struck_through.or(parent.struck_through),
+            obfuscated: self.obfuscated.or(parent.obfuscated),
+        }
+    }
+
+    fn apply_legacy_code(&mut self, code: char) {
+        match code.to_ascii_lowercase() {
+            'r' => *self = Self::default(),
+            'k' => self.obfuscated = Some(true),
+            'l' => self.bolded = Some(true),
+            'm' => self.struck_through = Some(true),
+            'n' => self.underlined = Some(true),
+            'o' => self.italicized = Some(true),
+            code => {
+                if let Some(color) = NamedTextColor::from_legacy_char(code) {
+                    *self = Self {
+                        color: Some(color.into()),
+                        ..Self::default()
+                    };
+                }
+            }
+        }
+    }
+
+    fn apply_sgr(&mut self, params: &str) {
+        // An empty parameter means 0, but ones that aren't numbers are unknown codes
+        let mut codes = params.split(';').map(|code| match code {
+            "" => Some(0),
+            code => code.parse::<u8>().ok(),
+        });
+        while let Some(code) = codes.next() {
+            let Some(code) = code else {
+                continue;
+            };
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bolded = Some(true),
+                3 => self.italicized = Some(true),
+                4 => self.underlined = Some(true),
+                30..=37 => self.color = Some(NamedTextColor::from_ansi(code - 30, false).into()),
+                90..=97 => self.color = Some(NamedTextColor::from_ansi(code - 90, true).into()),
+                38 => match codes.next().flatten() {
+                    Some(2) => {
+                        let rgb = [(); 3].map(|_| codes.next().flatten());
+                        if let [Some(red), Some(green), Some(blue)] = rgb {
+                            self.color =
+                                Some(TextColor::Hex(format!("#{red:02x}{green:02x}{blue:02x}")));
+                        }
+                    }
+                    Some(5) => {
+                        codes.next();
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum TextColor {
     Named(NamedTextColor),
@@ -281,13 +676,16 @@ impl TextColor {
 
 fn parse_hex(hex: &str) -> Result<[u8; 3]> {
     let hex = hex.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        bail!("the hex string must be 6 ASCII characters long");
+    }
     let red = u8::from_str_radix(&hex[0..2], 16).context("failed to parse the red value")?;
     let green = u8::from_str_radix(&hex[2..4], 16).context("failed to parse the green value")?;
     let blue = u8::from_str_radix(&hex[4..6], 16).context("failed to parse the blue value")?;
     Ok([red, green, blue])
 }
 
-#[derive(Serialize, Deserialize, Sequence)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize, Sequence)]
 #[serde(rename_all = "snake_case")]
 pub enum NamedTextColor {
     Black,
@@ -332,6 +730,32 @@ impl NamedTextColor {
         }
     }
 
+    pub fn from_legacy_char(char: char) -> Option<Self> {
+        enum_iterator::all::<Self>()
+            .find(|color| *color != Self::Reset && color.legacy_char() == char)
+    }
+
+    fn from_ansi(index: u8, bright: bool) -> Self {
+        match (index, bright) {
+            (0, false) => Self::Black,
+            (1, false) => Self::DarkRed,
+            (2, false) => Self::DarkGreen,
+            (3, false) => Self::Gold,
+            (4, false) => Self::DarkBlue,
+            (5, false) => Self::DarkPurple,
+            (6, false) => Self::DarkAqua,
+            (7, false) => Self::Gray,
+            (0, true) => Self::DarkGray,
+            (1, true) => Self::Red,
+            (2, true) => Self::Green,
+            (3, true) => Self::Yellow,
+            (4, true) => Self::Blue,
+            (5, true) => Self::LightPurple,
+            (6, true) => Self::Aqua,
+            _ => Self::White,
+        }
+    }
+
     pub fn vanilla(&self) -> [u8; 3] {
         match self {
             Self::Black => [0, 0, 0],
@@ -369,7 +793,7 @@ impl<D: Display> From<HexTextColor<D>> for TextColor {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum TextFont {
     #[serde(rename = "minecraft:default")]
     Default,
@@ -393,3 +817,204 @@ macro_rules! text {
         $crate::mc::text::Text::String(formatted)
     }};
 }
+
+#[macro_export]
+macro_rules! colored_text {
+    ($color:expr, $($arg:tt)*) => {
+        $crate::text!($($arg)*).color($color)
+    };
+}
+
+#[macro_export]
+macro_rules! bold_text {
+    ($($arg:tt)*) => {
+        $crate::text!($($arg)*).bolded(true)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interactive_text_round_trips() -> Result<()> {
+        let text = Text::from("Spawn")
+            .click_run_command("/spawn")
+            .hover_show_text(colored_text!(NamedTextColor::Gold, "Teleport"));
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(
+            r#"{"text":"Spawn","clickEvent":{"action":"run_command","value":"/spawn"},"hoverEvent":{"action":"show_text","contents":{"text":"Teleport","color":"gold"}}}"#,
+            json
+        );
+
+        let parsed = serde_json::from_str::<Text>(&json)?;
+        assert_eq!(json, parsed.to_json_string(JsonStringType::Short)?);
+        assert_eq!("Spawn", parsed.to_plain_string());
+        assert_eq!("Spawn", parsed.to_legacy_string());
+        Ok(())
+    }
+
+    #[test]
+    fn show_item_json() -> Result<()> {
+        let diamonds = Identifier::minecraft("diamond")?;
+        let text = Text::from("Loot").hover_show_item(diamonds.clone(), 3, None);
+        assert_eq!(
+            r#"{"text":"Loot","hoverEvent":{"action":"show_item","contents":{"id":"minecraft:diamond","count":3}}}"#,
+            text.to_json_string(JsonStringType::Short)?
+        );
+
+        let tag = Some("{display:{Name:'\"Shiny\"'}}".to_string());
+        let text = Text::from("Loot").hover_show_item(diamonds, 1, tag);
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(
+            r#"{"text":"Loot","hoverEvent":{"action":"show_item","contents":{"id":"minecraft:diamond","tag":"{display:{Name:'\"Shiny\"'}}"}}}"#,
+            json
+        );
+        let parsed = serde_json::from_str::<Text>(&json)?;
+        assert_eq!(json, parsed.to_json_string(JsonStringType::Short)?);
+        Ok(())
+    }
+
+    #[test]
+    fn show_entity_json() -> Result<()> {
+        let id = Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5")?;
+        let text = Text::from("Notch").hover_show_entity(
+            Identifier::minecraft("player")?,
+            id,
+            Some("Notch".into()),
+        );
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(
+            r#"{"text":"Notch","hoverEvent":{"action":"show_entity","contents":{"type":"minecraft:player","id":"069a79f4-44e9-4726-a5be-fca90e38aaf5","name":"Notch"}}}"#,
+            json
+        );
+        let parsed = serde_json::from_str::<Text>(&json)?;
+        assert_eq!(json, parsed.to_json_string(JsonStringType::Short)?);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_colors_reset_formatting() -> Result<()> {
+        let text = Text::from_legacy_string("\u{00a7}l\u{00a7}6Gold \u{00a7}obold\u{00a7}rplain");
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(
+            r#"["",{"text":"Gold ","color":"gold"},{"text":"bold","color":"gold","italic":true},"plain"]"#,
+            json
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_edge_cases() {
+        let text = Text::from_legacy_string("\u{00a7}zHi\u{00a7}");
+        assert_eq!("Hi", text.to_plain_string());
+        assert_eq!("Hi", text.to_legacy_string());
+
+        let legacy = "\u{00a7}cRed \u{00a7}9\u{00a7}nblue";
+        assert_eq!(legacy, Text::from_legacy_string(legacy).to_legacy_string());
+    }
+
+    #[test]
+    fn ansi_output() {
+        let text = Text::from("Minestodon")
+            .color(NamedTextColor::Gold)
+            .bolded(true)
+            .push_child(Text::from(" server").color(HexTextColor("#102030")))
+            .push_sequential(" is up");
+        assert_eq!(
+            "\x1b[1;38;2;255;170;0mMinestodon\x1b[0m\x1b[1;38;2;16;32;48m server\x1b[0m is up\x1b[0m",
+            text.to_ansi_string()
+        );
+        assert_eq!("plain\x1b[0m", Text::from("plain").to_ansi_string());
+    }
+
+    #[test]
+    fn hovered_segments_are_not_merged() {
+        let text = Text::from("a")
+            .push_sequential(Text::from("b").hover_show_text("B"))
+            .push_sequential("c")
+            .flatten();
+        let Text::Sequential(segments) = text else {
+            panic!("the segments were merged");
+        };
+        assert_eq!(4, segments.len());
+    }
+
+    #[test]
+    fn malformed_hex_falls_back_to_white() {
+        let text = Text::from("Hi").color(HexTextColor("#zzzzzz"));
+        assert_eq!("\u{00a7}fHi", text.to_legacy_string());
+        let text = Text::from("Hi").color(HexTextColor("#f"));
+        assert_eq!("\u{00a7}fHi", text.to_legacy_string());
+    }
+
+    #[test]
+    fn colored_text_macro() -> Result<()> {
+        let text = colored_text!(NamedTextColor::Gold, "{} players", 2);
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(r#"{"text":"2 players","color":"gold"}"#, json);
+        Ok(())
+    }
+
+    #[test]
+    fn bold_text_macro() -> Result<()> {
+        let text = bold_text!("Hello {}", "Minestodon");
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(r#"{"text":"Hello Minestodon","bold":true}"#, json);
+        Ok(())
+    }
+
+    #[test]
+    fn flatten_same_color_segments() -> Result<()> {
+        let text = (1..10).fold(colored_text!(NamedTextColor::Red, "0"), |text, i| {
+            text.push_sequential(colored_text!(NamedTextColor::Red, "{i}"))
+        });
+        let plain = text.to_plain_string();
+
+        let flattened = text.flatten();
+        assert_eq!(plain, flattened.to_plain_string());
+        let json = flattened.to_json_string(JsonStringType::Short)?;
+        assert_eq!(r#"{"text":"0123456789","color":"red"}"#, json);
+        Ok(())
+    }
+
+    #[test]
+    fn ansi_truecolor() -> Result<()> {
+        let text = Text::from_ansi("\x1b[1;38;2;255;128;0mHot");
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(r##"{"text":"Hot","color":"#ff8000","bold":true}"##, json);
+        Ok(())
+    }
+
+    #[test]
+    fn ansi_reset() -> Result<()> {
+        let text = Text::from_ansi("\x1b[31mError:\x1b[0m done\x1b[42m");
+        assert_eq!("Error: done", text.to_plain_string());
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(r#"["",{"text":"Error:","color":"dark_red"}," done"]"#, json);
+        Ok(())
+    }
+
+    #[test]
+    fn ansi_unparseable_codes_are_ignored() -> Result<()> {
+        let text = Text::from_ansi("\x1b[1;1000;256;31mStill bold\x1b[mreset");
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(
+            r#"["",{"text":"Still bold","color":"dark_red","bold":true},"reset"]"#,
+            json
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_text_lengths() -> Result<()> {
+        let text = Text::from("Caf\u{00e9} ")
+            .push_child(colored_text!(NamedTextColor::Green, "open"))
+            .push_sequential("!");
+        assert_eq!(10, text.plain_len());
+        let json = text.to_json_string(JsonStringType::Short)?;
+        assert_eq!(json.chars().count(), text.json_len()?);
+        assert!(text.json_len()? < Text::MAX_JSON_LEN);
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
 use crate::mc::registry::Registry;
 use crate::mc::Identifier;
+use anyhow::{bail, Context, Result};
 use minestodon_macros::{minecraft, minestodon};
 use serde::Serialize;
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct BlockPos {
     pub x: i32,
     pub y: i32,
@@ -15,14 +17,14 @@ impl BlockPos {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct Biome {
     #[serde(flatten)]
     pub weather: BiomeWeather,
     pub effects: BiomeEffects,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct BiomeWeather {
     pub precipitation: BiomePrecipitation,
     pub temperature: f32,
@@ -31,7 +33,7 @@ pub struct BiomeWeather {
     pub downfall: f32,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum BiomePrecipitation {
     None,
@@ -39,14 +41,14 @@ pub enum BiomePrecipitation {
     Snow,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum BiomeTemperatureModifier {
     None,
     Frozen,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct BiomeEffects {
     pub fog_color: i32,
     pub water_color: i32,
@@ -61,7 +63,28 @@ pub struct BiomeEffects {
     // TODO: Particles and sounds
 }
 
-#[derive(Serialize)]
+impl BiomeEffects {
+    pub fn validate(&self) -> Result<()> {
+        let colors = [
+            ("fog", Some(self.fog_color)),
+            ("water", Some(self.water_color)),
+            ("water fog", Some(self.water_fog_color)),
+            ("sky", Some(self.sky_color)),
+            ("foliage", self.foliage_color),
+            ("grass", self.grass_color),
+        ];
+        for (name, color) in colors {
+            if let Some(color) = color {
+                if !(0..=0xffffff).contains(&color) {
+                    bail!("the {name} color ({color:#x}) isn't a 24-bit RGB color");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum BiomeGrassColorModifier {
     None,
@@ -69,7 +92,7 @@ pub enum BiomeGrassColorModifier {
     Swamp,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct DimensionType {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fixed_time: Option<i64>,
@@ -97,7 +120,142 @@ pub struct DimensionType {
     pub monster_settings: MonsterSettings,
 }
 
-#[derive(Serialize)]
+impl DimensionType {
+    pub fn builder() -> DimensionTypeBuilder {
+        DimensionTypeBuilder::new()
+    }
+}
+
+pub struct DimensionTypeBuilder(DimensionType);
+
+impl DimensionTypeBuilder {
+    pub fn new() -> Self {
+        Self(DimensionType {
+            fixed_time: None,
+            sky_light: true,
+            ceiling: false,
+            ultra_warm: false,
+            natural: true,
+            coordinate_scale: 1.0,
+            bed_works: true,
+            respawn_anchor_works: false,
+            min_height: -64,
+            max_height: 384,
+            max_logical_height: 384,
+            infinite_burn_tag: InfiniteBurnTag::Overworld,
+            effects: DimensionEffects::Overworld,
+            ambient_light: 0.0,
+            monster_settings: MonsterSettings {
+                piglin_safe: false,
+                raids: true,
+                monster_spawn_light_level: 0,
+                monster_spawn_block_light_limit: 0,
+            },
+        })
+    }
+
+    pub fn fixed_time(mut self, fixed_time: Option<i64>) -> Self {
+        self.0.fixed_time = fixed_time;
+        self
+    }
+
+    pub fn sky_light(mut self, sky_light: bool) -> Self {
+        self.0.sky_light = sky_light;
+        self
+    }
+
+    pub fn ceiling(mut self, ceiling: bool) -> Self {
+        self.0.ceiling = ceiling;
+        self
+    }
+
+    pub fn ultra_warm(mut self, ultra_warm: bool) -> Self {
+        self.0.ultra_warm = ultra_warm;
+        self
+    }
+
+    pub fn natural(mut self, natural: bool) -> Self {
+        self.0.natural = natural;
+        self
+    }
+
+    pub fn coordinate_scale(mut self, coordinate_scale: f64) -> Self {
+        self.0.coordinate_scale = coordinate_scale;
+        self
+    }
+
+    pub fn bed_works(mut self, bed_works: bool) -> Self {
+        self.0.bed_works = bed_works;
+        self
+    }
+
+    pub fn respawn_anchor_works(mut self, respawn_anchor_works: bool) -> Self {
+        self.0.respawn_anchor_works = respawn_anchor_works;
+        self
+    }
+
+    pub fn min_height(mut self, min_height: i32) -> Self {
+        self.0.min_height = min_height;
+        self
+    }
+
+    pub fn max_height(mut self, max_height: i32) -> Self {
+        self.0.max_height = max_height;
+        self
+    }
+
+    pub fn max_logical_height(mut self, max_logical_height: i32) -> Self {
+        self.0.max_logical_height = max_logical_height;
+        self
+    }
+
+    pub fn infinite_burn_tag(mut self, infinite_burn_tag: InfiniteBurnTag) -> Self {
+        self.0.infinite_burn_tag = infinite_burn_tag;
+        self
+    }
+
+    pub fn effects(mut self, effects: DimensionEffects) -> Self {
+        self.0.effects = effects;
+        self
+    }
+
+    pub fn ambient_light(mut self, ambient_light: f32) -> Self {
+        self.0.ambient_light = ambient_light;
+        self
+    }
+
+    pub fn piglin_safe(mut self, piglin_safe: bool) -> Self {
+        self.0.monster_settings.piglin_safe = piglin_safe;
+        self
+    }
+
+    pub fn raids(mut self, raids: bool) -> Self {
+        self.0.monster_settings.raids = raids;
+        self
+    }
+
+    pub fn monster_spawn_light_level(mut self, monster_spawn_light_level: i32) -> Self {
+        self.0.monster_settings.monster_spawn_light_level = monster_spawn_light_level;
+        self
+    }
+
+    pub fn monster_spawn_block_light_limit(mut self, monster_spawn_block_light_limit: i32) -> Self {
+        self.0.monster_settings.monster_spawn_block_light_limit = monster_spawn_block_light_limit;
+        self
+    }
+
+    pub fn build(self) -> DimensionType {
+        self.0
+    }
+}
+
+impl Default for DimensionTypeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum DimensionEffects {
     #[serde(rename = "minecraft:overworld")]
     Overworld,
@@ -107,7 +265,7 @@ pub enum DimensionEffects {
     End,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub enum InfiniteBurnTag {
     #[serde(rename = "#minecraft:infiniburn_overworld")]
     Overworld,
@@ -117,7 +275,7 @@ pub enum InfiniteBurnTag {
     End,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 pub struct MonsterSettings {
     pub piglin_safe: bool,
     #[serde(rename = "has_raids")]
@@ -128,8 +286,17 @@ pub struct MonsterSettings {
 
 pub const BIOME: Identifier = minestodon!("tootlands");
 
+pub fn register_biome(registry: &Registry<Biome>, key: Identifier, biome: Biome) -> Result<()> {
+    biome
+        .effects
+        .validate()
+        .with_context(|| format!("the effects of {key} are invalid"))?;
+    registry.register(key, biome)
+}
+
 pub fn register_biomes(registry: &Registry<Biome>) {
-    registry.register(
+    register_biome(
+        registry,
         BIOME,
         Biome {
             weather: BiomeWeather {
@@ -148,10 +315,12 @@ pub fn register_biomes(registry: &Registry<Biome>) {
                 grass_color_modifier: None,
             },
         },
-    );
+    )
+    .expect("the built-in biome is invalid");
 
     // Clients will disconnect with an error if we don't send the plains biome
-    registry.register(
+    register_biome(
+        registry,
         minecraft!("plains"),
         Biome {
             weather: BiomeWeather {
@@ -170,35 +339,93 @@ pub fn register_biomes(registry: &Registry<Biome>) {
                 grass_color_modifier: None,
             },
         },
-    );
+    )
+    .expect("the built-in biome is invalid");
 }
 
 pub const DIMENSION_TYPE: Identifier = minestodon!("fediverse");
+// Profiles are always shown at noon; it keeps the same height so the spawn chunks fit both
+pub const PROFILE_DIMENSION_TYPE: Identifier = minestodon!("profile");
+
+#[derive(Clone, Debug)]
+pub struct World {
+    pub id: Identifier,
+    pub dimension_type: Identifier,
+}
+
+pub const TIMELINE_WORLD: World = World {
+    id: minestodon!("timeline"),
+    dimension_type: DIMENSION_TYPE,
+};
+
+pub const PROFILE_WORLD: World = World {
+    id: minestodon!("profile"),
+    dimension_type: PROFILE_DIMENSION_TYPE,
+};
+
+pub const WORLDS: [World; 2] = [TIMELINE_WORLD, PROFILE_WORLD];
 
 pub fn register_dimension_types(registry: &Registry<DimensionType>) {
-    registry.register(
-        DIMENSION_TYPE,
-        DimensionType {
-            fixed_time: None,
-            sky_light: true,
-            ceiling: false,
-            ultra_warm: false,
-            natural: true,
-            coordinate_scale: 1.0,
-            bed_works: false,
-            respawn_anchor_works: false,
-            min_height: -64,
-            max_height: 384,
-            max_logical_height: 384,
-            infinite_burn_tag: InfiniteBurnTag::Overworld,
-            effects: DimensionEffects::Overworld,
-            ambient_light: 0.0,
-            monster_settings: MonsterSettings {
-                piglin_safe: false,
-                raids: false,
-                monster_spawn_light_level: 0,
-                monster_spawn_block_light_limit: 0,
+    registry
+        .register(
+            DIMENSION_TYPE,
+            DimensionType::builder()
+                .bed_works(false)
+                .raids(false)
+                .build(),
+        )
+        .expect("the built-in dimension type is already registered");
+    registry
+        .register(
+            PROFILE_DIMENSION_TYPE,
+            DimensionType::builder()
+                .fixed_time(Some(6000))
+                .bed_works(false)
+                .raids(false)
+                .build(),
+        )
+        .expect("the built-in dimension type is already registered");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_biome_with_invalid_color() {
+        let registry = Registry::new(minestodon!("test_biomes"), |_| {});
+        registry.init();
+        let biome = Biome {
+            weather: BiomeWeather {
+                precipitation: BiomePrecipitation::None,
+                temperature: 0.5,
+                temperature_modifier: None,
+                downfall: 0.5,
             },
-        },
-    );
+            effects: BiomeEffects {
+                fog_color: 0x1ffffff,
+                water_color: 0x3f76e4,
+                water_fog_color: 0x050533,
+                sky_color: 0x78a7ff,
+                foliage_color: None,
+                grass_color: None,
+                grass_color_modifier: None,
+            },
+        };
+        assert!(register_biome(&registry, minestodon!("invalid"), biome).is_err());
+    }
+
+    #[test]
+    fn build_nether_like_dimension_type() {
+        let nether = DimensionType::builder()
+            .ceiling(true)
+            .ultra_warm(true)
+            .effects(DimensionEffects::Nether)
+            .build();
+        assert!(nether.ceiling);
+        assert!(nether.ultra_warm);
+        assert!(matches!(nether.effects, DimensionEffects::Nether));
+        assert!(nether.sky_light);
+        assert_eq!(384, nether.max_height);
+    }
 }
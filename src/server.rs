@@ -1,84 +1,386 @@
-use crate::mc::net::pre_login::{Listing, ListingPlayers, ListingVersion};
-use crate::mc::net::Connection;
-use crate::mc::player::Player;
-use crate::mc::text::{HexTextColor, Text};
-use anyhow::{Context, Result};
+use crate::mc::net;
+use crate::mc::net::play::chat::SystemChatMessage;
+use crate::mc::net::pre_login::{Listing, StatusResponse, StatusTemplate};
+use crate::mc::net::{Connection, TickOutcome};
+use crate::mc::player::{GameMode, Player, PlayerHandle};
+use crate::mc::text::{HexTextColor, NamedTextColor, Text};
+use crate::mc::world::BlockPos;
+use anyhow::{bail, Context, Result};
 use log::{debug, error, info, warn};
-use std::net::{TcpListener, TcpStream};
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Duration;
+use uuid::{Builder, Uuid};
+
+const LOG_TARGET: &str = "minestodon::server";
 
 pub struct Server(Arc<ServerInner>);
 
 struct ServerInner {
-    listener: TcpListener,
+    listeners: Listeners,
+    config: ServerConfig,
     next_entity_id: RwLock<i32>,
+    players: RwLock<HashMap<Uuid, PlayerHandle>>,
+    listing: RwLock<Listing>,
+    status_template: RwLock<Option<Arc<StatusTemplate>>>,
+    shutting_down: AtomicBool,
+    connection_count: AtomicUsize,
 }
 
 impl Server {
     pub fn bind(addr: &str) -> Result<Self> {
-        let listener = TcpListener::bind(addr)
-            .with_context(|| format!("failed to bind a new TCP listener to {addr}"))?;
+        Self::bind_with_config(addr, ServerConfig::default())
+    }
+
+    pub fn bind_with_config(addr: &str, config: ServerConfig) -> Result<Self> {
+        Self::bind_all_with_config(&[addr], config)
+    }
+
+    pub fn bind_all(addrs: &[&str]) -> Result<Self> {
+        Self::bind_all_with_config(addrs, ServerConfig::default())
+    }
+
+    pub fn bind_all_with_config(addrs: &[&str], config: ServerConfig) -> Result<Self> {
+        if addrs.is_empty() {
+            bail!("the server needs at least one address to bind to");
+        }
+        let listeners = addrs
+            .iter()
+            .map(|addr| {
+                TcpListener::bind(addr)
+                    .with_context(|| format!("failed to bind a new TCP listener to {addr}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        info!(target: LOG_TARGET, "Bound a new server to {}!", addrs.join(", "));
+        Ok(Self::with_listeners(Listeners::Blocking(listeners), config))
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn bind_async(addr: &str) -> Result<Self> {
+        Self::bind_all_async_with_config(&[addr], ServerConfig::default()).await
+    }
+
+    // Async servers get Tokio listeners of their own, so they can only be run with run_async
+    #[cfg(feature = "async")]
+    pub async fn bind_all_async_with_config(addrs: &[&str], config: ServerConfig) -> Result<Self> {
+        if addrs.is_empty() {
+            bail!("the server needs at least one address to bind to");
+        }
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("failed to bind a new TCP listener to {addr}"))?;
+            listeners.push(listener);
+        }
+
+        info!(target: LOG_TARGET, "Bound a new async server to {}!", addrs.join(", "));
+        Ok(Self::with_listeners(Listeners::Async(listeners), config))
+    }
 
-        info!("Bound a new server to {addr}!");
+    fn with_listeners(listeners: Listeners, config: ServerConfig) -> Self {
+        let listing = Self::default_listing(&config);
         let inner = ServerInner {
-            listener,
-            next_entity_id: RwLock::new(0),
+            listeners,
+            next_entity_id: RwLock::new(config.first_entity_id),
+            config,
+            players: RwLock::new(HashMap::new()),
+            listing: RwLock::new(listing),
+            status_template: RwLock::new(None),
+            shutting_down: AtomicBool::new(false),
+            connection_count: AtomicUsize::new(0),
         };
-        Ok(Self(Arc::new(inner)))
+        Self(Arc::new(inner))
     }
 
     pub fn run(&self) {
+        let Some(listeners) = self.0.listeners.blocking() else {
+            error!(target: LOG_TARGET, "The server was bound for async use, so it has to be run with run_async.");
+            return;
+        };
+        if let Some(interval) = self.config().connection_log_interval {
+            let clone = Self::clone(self);
+            let spawned =
+                thread::Builder::new()
+                    .name("connection-log".into())
+                    .spawn(move || loop {
+                        thread::sleep(interval);
+                        clone.log_connection_count();
+                    });
+            if let Err(err) = spawned {
+                error!(target: LOG_TARGET, "Failed to spawn the connection log thread:\nError: {err:?}");
+            }
+        }
+        // Every extra listener gets an accept thread of its own, the first one uses this thread
+        for index in 1..listeners.len() {
+            let clone = Self::clone(self);
+            let spawned = thread::Builder::new()
+                .name(format!("listener/{index}"))
+                .spawn(move || clone.accept_loop(index));
+            if let Err(err) = spawned {
+                error!(target: LOG_TARGET, "Failed to spawn a listener thread:\nError: {err:?}");
+            }
+        }
+        self.accept_loop(0);
+    }
+
+    fn accept_loop(&self, index: usize) {
+        let Some(listeners) = self.0.listeners.blocking() else {
+            unreachable!("only blocking servers have accept threads");
+        };
         loop {
-            if let Err(err) = self.tick() {
-                error!("Failed to tick the server:\nError: {err:?}");
+            if let Err(err) = self.tick(&listeners[index]) {
+                error!(target: LOG_TARGET, "Failed to tick the server:\nError: {err:?}");
             }
         }
     }
 
-    fn tick(&self) -> Result<()> {
-        let (stream, addr) = self
-            .0
-            .listener
+    fn tick(&self, listener: &TcpListener) -> Result<()> {
+        let (stream, addr) = listener
             .accept()
             .context("failed to accept the incoming connection")?;
-        debug!("Accepted a new connection from {addr}.");
+        debug!(target: LOG_TARGET, "Accepted a new connection from {addr}.");
 
         let clone = Self::clone(self);
         thread::Builder::new()
-            .name(format!("user/{addr}"))
+            .name((self.config().user_thread_name)(addr))
             .spawn(|| User::new(clone, stream).run())
             .context("failed to spawn a user thread")?;
         Ok(())
     }
 
+    #[cfg(feature = "async")]
+    pub async fn run_async(&self) -> Result<()> {
+        let Listeners::Async(listeners) = &self.0.listeners else {
+            bail!("the server was bound for blocking use, so it has to be run with run");
+        };
+        if let Some(interval) = self.config().connection_log_interval {
+            let clone = Self::clone(self);
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    clone.log_connection_count();
+                }
+            });
+        }
+
+        let mut tasks = Vec::with_capacity(listeners.len());
+        for index in 0..listeners.len() {
+            let clone = Self::clone(self);
+            tasks.push(tokio::spawn(
+                async move { clone.accept_loop_async(index).await },
+            ));
+        }
+        for task in tasks {
+            task.await.context("a listener task panicked")?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    async fn accept_loop_async(&self, index: usize) {
+        let Listeners::Async(listeners) = &self.0.listeners else {
+            unreachable!("only async servers have accept tasks");
+        };
+        loop {
+            if let Err(err) = self.tick_async(&listeners[index]).await {
+                error!(target: LOG_TARGET, "Failed to tick the server:\nError: {err:?}");
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn tick_async(&self, listener: &tokio::net::TcpListener) -> Result<()> {
+        let (stream, addr) = listener
+            .accept()
+            .await
+            .context("failed to accept the incoming connection")?;
+        debug!(target: LOG_TARGET, "Accepted a new connection from {addr}.");
+
+        let mut user = User::new_async(Self::clone(self), stream);
+        tokio::spawn(async move { user.run_async().await });
+        Ok(())
+    }
+
+    fn log_connection_count(&self) {
+        let addr = self
+            .local_addr()
+            .map_or_else(|_| "an unknown address".into(), |addr| addr.to_string());
+        debug!(target: LOG_TARGET, "Live connections on {addr}: {}", self.connection_count());
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        let addrs = self.local_addrs()?;
+        Ok(addrs[0])
+    }
+
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        let addrs = match &self.0.listeners {
+            Listeners::Blocking(listeners) => listeners
+                .iter()
+                .map(TcpListener::local_addr)
+                .collect::<io::Result<_>>(),
+            #[cfg(feature = "async")]
+            Listeners::Async(listeners) => listeners
+                .iter()
+                .map(tokio::net::TcpListener::local_addr)
+                .collect::<io::Result<_>>(),
+        };
+        addrs.context("failed to get the local address of a TCP listener")
+    }
+
+    fn default_listing(config: &ServerConfig) -> Listing {
+        let motd = Text::from("Minestodon!")
+            .color(HexTextColor("#6364ff"))
+            .bolded(true);
+        let mut listing = Listing::minimal(motd);
+        listing.players.max = config.max_players;
+        listing
+    }
+
     pub fn listing(&self) -> Listing {
-        Listing {
-            version: ListingVersion {
-                value: 761,
-                name: "Minestodon 1.19.3".into(),
-            },
-            players: ListingPlayers {
-                current: 0,
-                max: 1,
-                sample: None,
-            },
-            motd: Text::from("Minestodon!")
-                .color(HexTextColor("#6364ff"))
-                .bolded(true),
-            icon: None,
+        if self.is_shutting_down() {
+            let motd = Text::from("Server restarting").color(NamedTextColor::Red);
+            return Listing::minimal(motd);
         }
+
+        let mut listing = self.0.listing.read().unwrap().clone();
+        listing.players.current = self.online_count();
+        listing
+    }
+
+    pub fn set_listing(&self, listing: Listing) {
+        *self.0.listing.write().unwrap() = listing;
+        *self.0.status_template.write().unwrap() = None;
     }
 
     pub fn legacy_listing(&self) -> Listing {
         self.listing()
     }
 
-    pub fn next_entity_id(&self) -> i32 {
+    pub fn status_json(&self) -> Result<String> {
+        if self.is_shutting_down() {
+            return StatusResponse(self.listing()).to_json();
+        }
+
+        let template = self.status_template()?;
+        Ok(template.render(self.online_count()))
+    }
+
+    fn status_template(&self) -> Result<Arc<StatusTemplate>> {
+        if let Some(template) = &*self.0.status_template.read().unwrap() {
+            return Ok(Arc::clone(template));
+        }
+
+        let mut cached = self.0.status_template.write().unwrap();
+        if let Some(template) = &*cached {
+            return Ok(Arc::clone(template));
+        }
+        let listing = self.0.listing.read().unwrap();
+        let template =
+            StatusTemplate::new(&listing).context("failed to build the status template")?;
+        Ok(Arc::clone(cached.insert(Arc::new(template))))
+    }
+
+    pub fn begin_shutdown(&self) {
+        info!(target: LOG_TARGET, "Shutting down; new connections will be turned away.");
+        self.0.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.0.config
+    }
+
+    // The counter holds the next free ID, so i32::MAX itself is never handed out
+    pub fn next_entity_id(&self) -> Result<i32> {
         let mut locked = self.0.next_entity_id.write().unwrap();
         let next = *locked;
-        *locked += 1;
-        next
+        *locked = next
+            .checked_add(1)
+            .context("the server ran out of entity IDs")?;
+        Ok(next)
+    }
+
+    pub fn reserve_entity_ids(&self, count: i32) -> Result<RangeInclusive<i32>> {
+        if count <= 0 {
+            bail!("at least one entity ID has to be reserved, but {count} were requested");
+        }
+        let mut locked = self.0.next_entity_id.write().unwrap();
+        let first = *locked;
+        let next = first
+            .checked_add(count)
+            .with_context(|| format!("there aren't {count} entity IDs left after {first}"))?;
+        *locked = next;
+        Ok(first..=next - 1)
+    }
+
+    // Offline and custom UUIDs can collide, so a second player with a taken UUID is refused
+    pub fn register_player(&self, handle: PlayerHandle) -> bool {
+        let mut players = self.0.players.write().unwrap();
+        if players.contains_key(&handle.uuid) {
+            return false;
+        }
+        players.insert(handle.uuid, handle);
+        true
+    }
+
+    pub fn unregister_player(&self, uuid: &Uuid) {
+        let mut players = self.0.players.write().unwrap();
+        players.remove(uuid);
+    }
+
+    pub fn kick(&self, uuid: Uuid, reason: Text) -> bool {
+        let players = self.0.players.read().unwrap();
+        players.get(&uuid).is_some_and(|player| player.kick(reason))
+    }
+
+    // Returns how many players the message was queued for. It's checked up front since every
+    // player's thread would otherwise fail to send it at once
+    pub fn broadcast(&self, message: Text) -> Result<usize> {
+        message
+            .check_json_len()
+            .context("the message can't be sent")?;
+
+        let players = self.0.players.read().unwrap();
+        let mut queued = 0;
+        for player in players.values() {
+            let packet = SystemChatMessage {
+                text: message.clone(),
+                overlay: false,
+            };
+            if player.send(packet) {
+                queued += 1;
+            }
+        }
+        Ok(queued)
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.0.connection_count.load(Ordering::SeqCst)
+    }
+
+    pub fn online_count(&self) -> i32 {
+        let players = self.0.players.read().unwrap();
+        players.len().try_into().unwrap_or(i32::MAX)
+    }
+
+    pub fn online_players(&self) -> Vec<(Uuid, String)> {
+        let players = self.0.players.read().unwrap();
+        players
+            .values()
+            .map(|handle| (handle.uuid, handle.username.clone()))
+            .collect()
     }
 }
 
@@ -88,6 +390,106 @@ impl Clone for Server {
     }
 }
 
+enum Listeners {
+    Blocking(Vec<TcpListener>),
+    #[cfg(feature = "async")]
+    Async(Vec<tokio::net::TcpListener>),
+}
+
+impl Listeners {
+    fn blocking(&self) -> Option<&[TcpListener]> {
+        match self {
+            Self::Blocking(listeners) => Some(listeners),
+            #[cfg(feature = "async")]
+            Self::Async(_) => None,
+        }
+    }
+}
+
+pub struct ServerConfig {
+    pub kick_on_declined_forced_resource_pack: bool,
+    pub view_distance: i32,
+    pub max_players: i32,
+    pub compression_level: u32,
+    // Negative thresholds disable compression
+    pub compression_threshold: i32,
+    pub status_timeout: Duration,
+    pub max_packet_len: usize,
+    pub first_entity_id: i32,
+    pub spawn_pos: BlockPos,
+    pub spawn_angle: f32,
+    pub game_mode: GameMode,
+    pub last_game_mode: Option<GameMode>,
+    pub hardcore: bool,
+    pub reduced_debug_info: bool,
+    pub respawn_screen: bool,
+    pub debug_mode: bool,
+    pub flat_world: bool,
+    pub uuid_strategy: UuidStrategy,
+    pub on_container_closed: Option<fn(&mut Connection, u8)>,
+    pub on_sign_updated: Option<fn(&mut Connection, BlockPos, [String; 4])>,
+    pub proxy_protocol: bool,
+    pub user_thread_name: fn(SocketAddr) -> String,
+    // How often the live connection count is logged, if at all
+    pub connection_log_interval: Option<Duration>,
+}
+
+impl ServerConfig {
+    pub fn clamped_view_distance(&self) -> i32 {
+        self.view_distance.clamp(2, 32)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            kick_on_declined_forced_resource_pack: true,
+            view_distance: 3,
+            max_players: 20,
+            compression_level: 6,
+            compression_threshold: Connection::COMPRESSION_THRESHOLD,
+            status_timeout: Connection::STATUS_TIMEOUT,
+            max_packet_len: Connection::MAX_PACKET_LEN,
+            first_entity_id: 0,
+            spawn_pos: BlockPos::new(0, 0, 0),
+            spawn_angle: 0.0,
+            game_mode: GameMode::Adventure,
+            last_game_mode: None,
+            hardcore: false,
+            reduced_debug_info: false,
+            respawn_screen: true,
+            debug_mode: false,
+            flat_world: true,
+            uuid_strategy: UuidStrategy::Random,
+            on_container_closed: None,
+            on_sign_updated: None,
+            proxy_protocol: false,
+            user_thread_name: |addr| format!("user/{addr}"),
+            connection_log_interval: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum UuidStrategy {
+    Random,
+    Offline,
+    Custom(fn(&str) -> Uuid),
+}
+
+impl UuidStrategy {
+    pub fn assign(&self, username: &str) -> Uuid {
+        match self {
+            UuidStrategy::Random => Uuid::new_v4(),
+            UuidStrategy::Offline => {
+                let digest = Md5::digest(format!("OfflinePlayer:{username}"));
+                Builder::from_md5_bytes(digest.into()).into_uuid()
+            }
+            UuidStrategy::Custom(assign) => assign(username),
+        }
+    }
+}
+
 pub struct User {
     pub server: Server,
     connection: ConnectionOrPlayer,
@@ -95,7 +497,24 @@ pub struct User {
 
 impl User {
     pub fn new(server: Server, stream: TcpStream) -> Self {
-        let connection = Connection::new(stream);
+        Self::with_connection(server, Connection::new(stream))
+    }
+
+    #[cfg(feature = "async")]
+    pub fn new_async(server: Server, stream: tokio::net::TcpStream) -> Self {
+        Self::with_connection(server, Connection::new_async(stream))
+    }
+
+    fn with_connection(server: Server, mut connection: Connection) -> Self {
+        connection.set_compression_level(server.config().compression_level);
+        connection.set_compression_threshold(server.config().compression_threshold);
+        connection.set_status_timeout(server.config().status_timeout);
+        connection.set_max_packet_len(server.config().max_packet_len);
+        if server.config().proxy_protocol {
+            connection.expect_proxy_header();
+        }
+        let live = server.0.connection_count.fetch_add(1, Ordering::SeqCst) + 1;
+        debug!(target: LOG_TARGET, "Opened a connection. ({live} live)");
         Self {
             server,
             connection: ConnectionOrPlayer::new(connection),
@@ -103,30 +522,93 @@ impl User {
     }
 
     pub fn run(&mut self) {
-        loop {
-            match self.tick() {
-                Err(err) => {
-                    error!("Failed to tick the user:\nError: {err:?}");
-                    if let Err(err) = self.connection.connection_mut().send_error_kick(err) {
-                        warn!("Failed to kick the player after an error: {err:?}");
-                    }
-                    break;
+        let disconnected = loop {
+            let result = self.tick();
+            if let Some(disconnected) = self.stop_after(result) {
+                break disconnected;
+            }
+        };
+
+        // Kicks are usually the last thing sent, so they have to make it out before closing
+        if !disconnected {
+            if let Err(err) = self.connection.connection_mut().finish_writes() {
+                warn!(target: LOG_TARGET, "Failed to finish writing before closing: {err:?}");
+            }
+        }
+        self.close();
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self) {
+        let disconnected = loop {
+            let result = self.tick_async().await;
+            if let Some(disconnected) = self.stop_after(result) {
+                break disconnected;
+            }
+        };
+
+        if !disconnected {
+            if let Err(err) = self.connection.connection_mut().finish_writes_async().await {
+                warn!(target: LOG_TARGET, "Failed to finish writing before closing: {err:?}");
+            }
+        }
+        self.close();
+    }
+
+    // Returns whether the client disconnected once the user should stop ticking
+    fn stop_after(&mut self, result: Result<ConnectionAction>) -> Option<bool> {
+        match result {
+            Err(err) if net::is_disconnect(&err) => {
+                debug!(target: LOG_TARGET, "The client disconnected mid-write: {err:?}");
+                Some(true)
+            }
+            Err(err) => {
+                error!(target: LOG_TARGET, "Failed to tick the user:\nError: {err:?}");
+                if let Err(err) = self.connection.connection_mut().send_error_kick(err) {
+                    warn!(target: LOG_TARGET, "Failed to kick the player after an error: {err:?}");
                 }
-                Ok(ConnectionAction::Close) => break,
-                _ => (),
+                Some(false)
             }
+            Ok(ConnectionAction::Close) => Some(false),
+            _ => None,
+        }
+    }
+
+    fn close(&mut self) {
+        if let ConnectionOrPlayer::Player(player) = &self.connection {
+            self.server.unregister_player(&player.uuid());
         }
-        debug!("Closing the connection.");
+        debug!(target: LOG_TARGET, "Closing the connection.");
     }
 
     fn tick(&mut self) -> Result<ConnectionAction> {
-        let action = self
+        let outcome = self
             .connection
             .connection_mut()
             .tick(&self.server)
             .context("failed to tick the Minecraft connection")?;
+        self.handle_outcome(outcome)
+    }
 
-        match action {
+    #[cfg(feature = "async")]
+    async fn tick_async(&mut self) -> Result<ConnectionAction> {
+        let outcome = self
+            .connection
+            .connection_mut()
+            .tick_async(&self.server)
+            .await
+            .context("failed to tick the Minecraft connection")?;
+        self.handle_outcome(outcome)
+    }
+
+    fn handle_outcome(&mut self, outcome: TickOutcome) -> Result<ConnectionAction> {
+        match outcome.action {
+            ConnectionAction::DoNothing => match &mut self.connection {
+                ConnectionOrPlayer::Player(player) => player
+                    .tick(&self.server)
+                    .context("failed to tick the player"),
+                ConnectionOrPlayer::Connection(_) => Ok(ConnectionAction::DoNothing),
+            },
             ConnectionAction::CreatePlayer { username } => {
                 let ConnectionOrPlayer::Connection(connection) = &mut self.connection else {
                     panic!("the user is already a player");
@@ -135,11 +617,23 @@ impl User {
 
                 let server = Server::clone(&self.server);
                 let mut player = Player::new(connection, username, server);
+                if !self.server.register_player(player.handle()) {
+                    warn!(target: LOG_TARGET, "Refusing {} since their UUID is already online.", player.username);
+                    let reason = Text::from("A player with your UUID is already online!");
+                    let kicked = player.connection.send_kick(reason);
+                    self.connection = ConnectionOrPlayer::new(player.connection);
+                    kicked.context("failed to refuse the duplicate player")?;
+                    return Ok(ConnectionAction::Close);
+                }
+
+                // Registered first so the player is unregistered even if joining fails
+                self.connection = ConnectionOrPlayer::Player(player);
+                let ConnectionOrPlayer::Player(player) = &mut self.connection else {
+                    unreachable!();
+                };
                 player
                     .finish_joining()
                     .context("failed to finish joining")?;
-
-                self.connection = ConnectionOrPlayer::Player(player);
                 Ok(ConnectionAction::DoNothing)
             }
             action => Ok(action),
@@ -147,6 +641,18 @@ impl User {
     }
 }
 
+impl Drop for User {
+    fn drop(&mut self) {
+        let live = self
+            .server
+            .0
+            .connection_count
+            .fetch_sub(1, Ordering::SeqCst)
+            - 1;
+        debug!(target: LOG_TARGET, "Closed a connection. ({live} live)");
+    }
+}
+
 enum ConnectionOrPlayer {
     Connection(Option<Connection>),
     Player(Player),
@@ -170,3 +676,394 @@ pub enum ConnectionAction {
     Close,
     CreatePlayer { username: String },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mc::net::login::LoginDisconnect;
+    use crate::mc::net::packet_io::{PacketReadExt, PacketWriteExt};
+    use crate::mc::net::play::PlayDisconnect;
+    use crate::mc::net::pre_login::StatusRequest;
+    use crate::mc::net::{test_util, ConnectionState, PacketFromClient, PacketFromServer};
+    use crate::mc::registry;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use serde_json::Value;
+    use std::time::Instant;
+
+    #[test]
+    fn status_json_contains_listing() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let status: Value = serde_json::from_str(&server.status_json()?)?;
+        assert!(status["description"].is_object());
+        assert!(status["players"]["online"].is_number());
+        assert_eq!(761, status["version"]["protocol"]);
+        Ok(())
+    }
+
+    #[test]
+    fn status_template_is_cached() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let first = server.status_template()?;
+        assert!(Arc::ptr_eq(&first, &server.status_template()?));
+
+        server.set_listing(Listing::minimal("Changed".into()));
+        assert!(!Arc::ptr_eq(&first, &server.status_template()?));
+
+        server.register_player(player_handle(&server, "first"));
+        let status: Value = serde_json::from_str(&server.status_json()?)?;
+        assert_eq!("Changed", status["description"]);
+        assert_eq!(1, status["players"]["online"]);
+        Ok(())
+    }
+
+    #[test]
+    fn status_during_shutdown() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        server.begin_shutdown();
+
+        let (mut connection, mut client) = test_util::connection();
+        connection.set_state(ConnectionState::Status)?;
+        connection.decode_and_handle_packet(StatusRequest::ID, &mut &[][..], &server)?;
+
+        let (_, body) = test_util::read_packet(&mut client, false)?;
+        let status: Value = serde_json::from_str(&(&body[..]).read_string()?)?;
+        assert_eq!(0, status["players"]["online"]);
+        assert_eq!("Server restarting", status["description"]["text"]);
+        assert_eq!("red", status["description"]["color"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn status_ping_async() -> Result<()> {
+        let server = Server::bind_async("127.0.0.1:0").await?;
+        let addr = server.local_addr()?;
+        tokio::spawn(async move { server.run_async().await });
+
+        let status = tokio::task::spawn_blocking(move || request_status(addr)).await??;
+        assert_eq!(761, status["version"]["protocol"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn login_and_play_async() -> Result<()> {
+        use crate::mc::net::login::LoginSuccess;
+        use crate::mc::net::play::setup::PlayLogin;
+
+        registry::init();
+        let config = ServerConfig {
+            compression_threshold: -1,
+            ..Default::default()
+        };
+        let server = Server::bind_all_async_with_config(&["127.0.0.1:0"], config).await?;
+        let addr = server.local_addr()?;
+        let clone = Server::clone(&server);
+        tokio::spawn(async move { clone.run_async().await });
+
+        let ids = tokio::task::spawn_blocking(move || -> Result<Vec<i32>> {
+            let mut client = log_in(addr, "asyncer")?;
+            (0..2)
+                .map(|_| Ok(test_util::read_packet(&mut client, false)?.0))
+                .collect()
+        })
+        .await??;
+        assert_eq!(vec![LoginSuccess::ID, PlayLogin::ID], ids);
+        assert_eq!(1, server.online_count());
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn blocking_servers_refuse_run_async() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        assert!(server.run_async().await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn status_on_every_listener() -> Result<()> {
+        let server = Server::bind_all(&["127.0.0.1:0", "127.0.0.1:0"])?;
+        let addrs = server.local_addrs()?;
+        assert_eq!(2, addrs.len());
+        thread::spawn(move || server.run());
+
+        for addr in addrs {
+            let status = request_status(addr)?;
+            assert_eq!(761, status["version"]["protocol"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn connection_count_follows_connections() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let addr = server.local_addr()?;
+        let clone = Server::clone(&server);
+        thread::spawn(move || clone.run());
+
+        let wait_for_count = |expected| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while server.connection_count() != expected && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            server.connection_count()
+        };
+        let client = TcpStream::connect(addr)?;
+        assert_eq!(1, wait_for_count(1));
+        drop(client);
+        assert_eq!(0, wait_for_count(0));
+        Ok(())
+    }
+
+    #[test]
+    fn user_threads_use_the_configured_name() -> Result<()> {
+        static NAMED: AtomicBool = AtomicBool::new(false);
+        registry::init();
+        let config = ServerConfig {
+            user_thread_name: |_| "custom-user".into(),
+            uuid_strategy: UuidStrategy::Custom(|_| {
+                let name = thread::current().name().map(str::to_string);
+                NAMED.store(name.as_deref() == Some("custom-user"), Ordering::SeqCst);
+                Uuid::new_v4()
+            }),
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let addr = server.local_addr()?;
+        thread::spawn(move || server.run());
+
+        let mut client = log_in(addr, "named")?;
+        test_util::read_packet(&mut client, false)?;
+        assert!(NAMED.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn connection_count_is_logged_periodically() -> Result<()> {
+        test_util::capture_logs();
+        let config = ServerConfig {
+            connection_log_interval: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let message = format!("Live connections on {}: 0", server.local_addr()?);
+        thread::spawn(move || server.run());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while test_util::logged_target(&message).is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            Some(LOG_TARGET),
+            test_util::logged_target(&message).as_deref()
+        );
+        Ok(())
+    }
+
+    // Sends a handshake and login start, leaving the responses for the caller to read
+    fn log_in(addr: SocketAddr, username: &str) -> Result<TcpStream> {
+        let mut client = TcpStream::connect(addr)?;
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        handshake.write_u16::<BigEndian>(addr.port())?;
+        handshake.write_var(2)?;
+        test_util::write_packet(&mut client, 0x00, &handshake, false)?;
+        let mut login_start = vec![];
+        login_start.write_str(username)?;
+        login_start.write_bool(false)?;
+        test_util::write_packet(&mut client, 0x00, &login_start, false)?;
+        Ok(client)
+    }
+
+    fn request_status(addr: SocketAddr) -> Result<Value> {
+        let mut client = TcpStream::connect(addr)?;
+        let mut handshake = vec![];
+        handshake.write_var(761)?;
+        handshake.write_str("localhost")?;
+        handshake.write_u16::<BigEndian>(addr.port())?;
+        handshake.write_var(1)?;
+        test_util::write_packet(&mut client, 0x00, &handshake, false)?;
+        test_util::write_packet(&mut client, StatusRequest::ID, &[], false)?;
+
+        let (_, body) = test_util::read_packet(&mut client, false)?;
+        Ok(serde_json::from_str(&(&body[..]).read_string()?)?)
+    }
+
+    #[test]
+    fn custom_uuid_strategy() -> Result<()> {
+        const FIXED: Uuid = Uuid::from_u128(0x1234_5678_9abc_def0_1234_5678_9abc_def0);
+        let config = ServerConfig {
+            uuid_strategy: UuidStrategy::Custom(|_| FIXED),
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+
+        let (connection, _client) = test_util::connection();
+        let player = Player::new(connection, "Notch".into(), server);
+        assert_eq!(FIXED, player.uuid());
+        Ok(())
+    }
+
+    #[test]
+    fn offline_uuid_strategy() {
+        let uuid = UuidStrategy::Offline.assign("Notch");
+        assert_eq!("b50ad385-829d-3141-a216-7e7d7539ba7f", uuid.to_string());
+    }
+
+    #[test]
+    fn reserved_entity_ids_are_skipped() -> Result<()> {
+        let config = ServerConfig {
+            first_entity_id: 1000,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        assert_eq!(1000, server.next_entity_id()?);
+
+        let reserved = server.reserve_entity_ids(16)?;
+        assert_eq!(1001..=1016, reserved);
+        assert_eq!(1017, server.next_entity_id()?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_entity_id_reservations() -> Result<()> {
+        let config = ServerConfig {
+            first_entity_id: i32::MAX - 10,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        assert!(server.reserve_entity_ids(0).is_err());
+        assert!(server.reserve_entity_ids(-5).is_err());
+        assert!(server.reserve_entity_ids(11).is_err());
+        assert_eq!(i32::MAX - 10..=i32::MAX - 2, server.reserve_entity_ids(9)?);
+        assert_eq!(i32::MAX - 1, server.next_entity_id()?);
+        assert!(server.next_entity_id().is_err());
+        assert!(server.reserve_entity_ids(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn listing_reports_players() -> Result<()> {
+        let config = ServerConfig {
+            max_players: 50,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        server.register_player(player_handle(&server, "first"));
+
+        let listing = server.listing();
+        assert_eq!(1, listing.players.current);
+        assert_eq!(50, listing.players.max);
+        Ok(())
+    }
+
+    #[test]
+    fn online_players_snapshot() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let first = player_handle(&server, "first");
+        let second = player_handle(&server, "second");
+        server.register_player(first.clone());
+        server.register_player(second.clone());
+
+        let mut online = server.online_players();
+        online.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            vec![
+                (first.uuid, first.username),
+                (second.uuid, second.username.clone())
+            ],
+            online
+        );
+
+        server.unregister_player(&first.uuid);
+        assert_eq!(
+            vec![(second.uuid, second.username)],
+            server.online_players()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn kick_by_uuid() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let (connection, mut client) = test_util::play_connection();
+        let mut player = Player::new(connection, "kicked".into(), Server::clone(&server));
+        server.register_player(player.handle());
+
+        assert!(!server.kick(Uuid::new_v4(), "Nobody".into()));
+        assert!(server.kick(player.uuid(), "Goodbye".into()));
+        let action = player.tick(&server)?;
+        assert!(matches!(action, ConnectionAction::Close));
+
+        let (id, body) = test_util::read_packet(&mut client, false)?;
+        assert_eq!(PlayDisconnect::ID, id);
+        assert_eq!("\"Goodbye\"", (&body[..]).read_string()?);
+        Ok(())
+    }
+
+    #[test]
+    fn broadcast_reaches_every_player() -> Result<()> {
+        let server = Server::bind("127.0.0.1:0")?;
+        let mut players = vec![];
+        for username in ["first", "second"] {
+            let (connection, client) = test_util::play_connection();
+            let player = Player::new(connection, username.into(), Server::clone(&server));
+            server.register_player(player.handle());
+            players.push((player, client));
+        }
+
+        let oversized = Text::from("a".repeat(Text::MAX_JSON_LEN));
+        assert!(server.broadcast(oversized).is_err());
+        assert_eq!(2, server.broadcast("Hello everyone".into())?);
+        for (player, client) in &mut players {
+            player.tick(&server)?;
+            let (id, body) = test_util::read_packet(client, false)?;
+            assert_eq!(SystemChatMessage::ID, id);
+            let mut body = &body[..];
+            assert_eq!("\"Hello everyone\"", body.read_string()?);
+            assert!(!body.read_bool()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_offline_logins_are_refused() -> Result<()> {
+        registry::init();
+        let config = ServerConfig {
+            uuid_strategy: UuidStrategy::Offline,
+            ..Default::default()
+        };
+        let server = Server::bind_with_config("127.0.0.1:0", config)?;
+        let join = || -> Result<(User, test_util::DuplexStream, ConnectionAction)> {
+            let (mut connection, client) = test_util::connection();
+            connection.set_state(ConnectionState::Login)?;
+            let mut user = User::with_connection(Server::clone(&server), connection);
+            let mut outcome = TickOutcome::new(0);
+            outcome.action = ConnectionAction::CreatePlayer {
+                username: "Notch".into(),
+            };
+            let action = user.handle_outcome(outcome)?;
+            Ok((user, client, action))
+        };
+
+        let (mut first, _first_client, action) = join()?;
+        assert!(matches!(action, ConnectionAction::DoNothing));
+        let (mut second, mut second_client, action) = join()?;
+        assert!(matches!(action, ConnectionAction::Close));
+        let (id, _) = test_util::read_packet(&mut second_client, false)?;
+        assert_eq!(LoginDisconnect::ID, id);
+
+        second.close();
+        assert_eq!(1, server.online_count());
+        first.close();
+        assert_eq!(0, server.online_count());
+        Ok(())
+    }
+
+    fn player_handle(server: &Server, username: &str) -> PlayerHandle {
+        let (connection, _client) = test_util::connection();
+        Player::new(connection, username.into(), Server::clone(server)).handle()
+    }
+}